@@ -0,0 +1,383 @@
+use std::{
+    collections::HashSet,
+    env,
+    fs::{self, create_dir_all, File},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, SystemTime}
+};
+
+use json::{object, JsonValue};
+use sha1::{Digest, Sha1};
+
+use crate::{export::listFiles, http, state::{self, STATE_FILE}};
+
+/// Lists the installation directories whose install state protects cache entries from pruning, one absolute path per line
+const INSTALLS_FILE: &str = "installs.txt";
+
+static DISABLED: AtomicBool = AtomicBool::new(false);
+static REFRESH: AtomicBool = AtomicBool::new(false);
+/// Seconds cached API responses are used without revalidating them
+static METADATA_TTL: AtomicU64 = AtomicU64::new(10 * 60);
+
+/// Stops downloads and API requests from reading or populating the cache
+pub fn disable() {
+    DISABLED.store(true, Ordering::Relaxed);
+}
+
+/// Revalidates every cached API response regardless of its age
+pub fn refresh() {
+    REFRESH.store(true, Ordering::Relaxed);
+}
+
+pub fn setMetadataTtl(ttl: Duration) {
+    METADATA_TTL.store(ttl.as_secs(), Ordering::Relaxed);
+}
+
+/// $XDG_CACHE_HOME/modpacks-rs, ~/.cache/modpacks-rs or %LOCALAPPDATA%\modpacks-rs
+pub fn cacheDir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os(if cfg!(windows) { "LOCALAPPDATA" } else { "HOME" }).map(|home| {
+            let mut path = PathBuf::from(home);
+            if !cfg!(windows) {
+                path.push(".cache");
+            }
+            path
+        }))?;
+    Some(base.join("modpacks-rs"))
+}
+
+/// Where a file with the given SHA-1 is kept, split by the first two characters of the hash like git objects
+fn entryPath(sha1: &str) -> Option<PathBuf> {
+    if sha1.len() != 40 || !sha1.chars().all(|it| it.is_ascii_hexdigit()) || DISABLED.load(Ordering::Relaxed) {
+        return None;
+    }
+    let sha1 = sha1.to_ascii_lowercase();
+    Some(cacheDir()?.join("files").join(&sha1[..2]).join(sha1))
+}
+
+/// Places the cached file with the given SHA-1 at `dest` and returns its contents,
+/// entries that no longer match their hash are removed
+pub fn fetch(sha1: &str, dest: &Path) -> Option<Vec<u8>> {
+    let entry = entryPath(sha1)?;
+    let raw = fs::read(&entry).ok()?;
+    let mut hasher = Sha1::new();
+    hasher.update(&raw);
+    if !hex::encode(hasher.finalize()).eq_ignore_ascii_case(sha1) {
+        eprintln!("Removing corrupt cache entry {:?}", entry);
+        let _ = fs::remove_file(&entry);
+        return None;
+    }
+    link(&entry, dest, &raw).ok()?;
+    // Pruning by age goes by when an entry was last used
+    let _ = File::options().write(true).open(&entry).and_then(|file| file.set_modified(SystemTime::now()));
+    Some(raw)
+}
+
+/// Adds a verified download to the cache and places it at `dest`, a failure to cache only costs a later download
+pub fn store(sha1: &str, dest: &Path, raw: &[u8]) -> Result<(), String> {
+    let entry = match entryPath(sha1) {
+        Some(entry) => entry,
+        None => return write(dest, raw)
+    };
+    if let Err(error) = writeEntry(&entry, raw) {
+        eprintln!("Failed to cache {:?}: {}", dest, error);
+        return write(dest, raw);
+    }
+    link(&entry, dest, raw)
+}
+
+/// Entries are written under a temporary name first so concurrent downloads never see a partial file
+fn writeEntry(entry: &Path, raw: &[u8]) -> Result<(), String> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    if entry.exists() {
+        return Ok(());
+    }
+    create_dir_all(entry.parent().unwrap())
+        .map_err(|it| format!("Failed to create cache directory: {:?}", it))?;
+    let temp = entry.with_extension(format!("{}-{}.part", process::id(), COUNTER.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&temp, raw)
+        .and_then(|_| fs::rename(&temp, entry))
+        .map_err(|it| {
+            let _ = fs::remove_file(&temp);
+            format!("Failed to write cache entry: {:?}", it)
+        })
+}
+
+/// Hard links archives like mod jars to `dest` so packs sharing them share the disk space, other files such as configs
+/// are copied since editing a hard linked file would change it in every pack, as is everything when the cache is on another filesystem
+fn link(entry: &Path, dest: &Path, raw: &[u8]) -> Result<(), String> {
+    let _ = fs::remove_file(dest);
+    let archive = dest.extension().is_some_and(|extension| extension == "jar" || extension == "zip");
+    if archive && fs::hard_link(entry, dest).is_ok() {
+        return Ok(());
+    }
+    write(dest, raw)
+}
+
+fn write(dest: &Path, raw: &[u8]) -> Result<(), String> {
+    fs::write(dest, raw).map_err(|it| format!("Failed to write to file: {:?}", it))
+}
+
+/// Gets the body of an API response, cached responses are used while younger than the TTL and otherwise revalidated
+/// with their ETag or Last-Modified, falling back to the cached response when the server can't be reached
+pub fn getMetadata(url: &str, headers: &[(&str, &str)], what: &str) -> Result<String, String> {
+    let entryPath = match cacheDir().filter(|_| !DISABLED.load(Ordering::Relaxed)) {
+        Some(cacheDir) => {
+            let mut hasher = Sha1::new();
+            hasher.update(url.as_bytes());
+            cacheDir.join("metadata").join(format!("{}.json", hex::encode(hasher.finalize())))
+        }
+        None => return fetchMetadata(url, headers, what, &JsonValue::Null).map(|fetched| fetched.body.unwrap_or_default())
+    };
+    let cached = fs::read_to_string(&entryPath).ok()
+        .and_then(|contents| json::parse(&contents).ok())
+        .filter(|cached| cached["url"] == url)
+        .unwrap_or(JsonValue::Null);
+    let age = fs::metadata(&entryPath).and_then(|metadata| metadata.modified()).ok()
+        .and_then(|modified| SystemTime::now().duration_since(modified).ok());
+    if !cached.is_null() && !REFRESH.load(Ordering::Relaxed) && age.is_some_and(|age| age.as_secs() < METADATA_TTL.load(Ordering::Relaxed)) {
+        return Ok(cached["body"].to_string());
+    }
+    let fetched = match fetchMetadata(url, headers, what, &cached) {
+        Ok(response) => response,
+        Err(error) if !cached.is_null() => {
+            eprintln!("{error}, using the cached response");
+            return Ok(cached["body"].to_string());
+        }
+        Err(error) => return Err(error)
+    };
+    // Not modified, the cached response is fresh again
+    let notModified = fetched.body.is_none();
+    let body = fetched.body.unwrap_or_else(|| cached["body"].to_string());
+    // Validators a 304 leaves out are still valid, such as the Last-Modified it rarely repeats
+    let cachedValidator = |key: &str| cached[key].as_str().filter(|_| notModified).map(str::to_string);
+    let etag = fetched.etag.or_else(|| cachedValidator("etag"));
+    let lastModified = fetched.lastModified.or_else(|| cachedValidator("lastModified"));
+    let entry = object! { url: url, etag: etag, lastModified: lastModified, body: body.as_str() };
+    let written = create_dir_all(entryPath.parent().unwrap())
+        .and_then(|_| fs::write(&entryPath, json::stringify(entry)));
+    if let Err(error) = written {
+        eprintln!("Failed to cache {what}: {:?}", error);
+    }
+    Ok(body)
+}
+
+/// An API response along with the validators to revalidate it with later
+struct Fetched {
+    /// None when the cached response wasn't modified
+    body: Option<String>,
+    etag: Option<String>,
+    lastModified: Option<String>
+}
+
+/// Requests `url`, conditionally if `cached` has validators
+fn fetchMetadata(url: &str, headers: &[(&str, &str)], what: &str, cached: &JsonValue) -> Result<Fetched, String> {
+    let mut request = http::get(url);
+    for (name, value) in headers {
+        request = request.set(name, value);
+    }
+    if let Some(etag) = cached["etag"].as_str() {
+        request = request.set("If-None-Match", etag);
+    }
+    if let Some(lastModified) = cached["lastModified"].as_str() {
+        request = request.set("If-Modified-Since", lastModified);
+    }
+    let resp = request.call().map_err(|it| format!("Failed to GET {what}: {:?}", it))?;
+    let etag = resp.header("etag").map(str::to_string);
+    let lastModified = resp.header("last-modified").map(str::to_string);
+    let body = if resp.status() == 304 {
+        None
+    } else {
+        Some(resp.into_string().map_err(|it| format!("Failed to parse {what} as string: {:?}", it))?)
+    };
+    Ok(Fetched { body, etag, lastModified })
+}
+
+/// Remembers an installation so the cache entries its install state references are never pruned
+pub fn track(dir: &Path) -> Result<(), String> {
+    let cacheDir = match cacheDir() {
+        Some(cacheDir) => cacheDir,
+        None => return Ok(())
+    };
+    let dir = dir.canonicalize().map_err(|it| format!("Failed to resolve {:?}: {:?}", dir, it))?;
+    let dir = dir.to_string_lossy().to_string();
+    if readInstalls(&cacheDir).contains(&dir) {
+        return Ok(());
+    }
+    create_dir_all(&cacheDir)
+        .and_then(|_| File::options().create(true).append(true).open(cacheDir.join(INSTALLS_FILE)))
+        .and_then(|mut file| writeln!(file, "{dir}"))
+        .map_err(|it| format!("Failed to track installation: {:?}", it))
+}
+
+fn readInstalls(cacheDir: &Path) -> Vec<String> {
+    fs::read_to_string(cacheDir.join(INSTALLS_FILE))
+        .map(|contents| contents.lines().filter(|line| !line.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+/// The hashes of every file recorded by a tracked installation, forgetting installations that no longer exist
+fn getReferenced(cacheDir: &Path) -> Result<HashSet<String>, String> {
+    let installs = readInstalls(cacheDir);
+    let mut kept: Vec<&String> = vec![];
+    let mut referenced: HashSet<String> = HashSet::new();
+    for install in &installs {
+        let dir = Path::new(install);
+        if !dir.join(STATE_FILE).exists() {
+            continue;
+        }
+        kept.push(install);
+        referenced.extend(state::read(dir)?["files"].members().map(|file| file["sha1"].to_string().to_ascii_lowercase()));
+    }
+    if kept.len() != installs.len() {
+        let contents: String = kept.iter().map(|install| format!("{install}\n")).collect();
+        fs::write(cacheDir.join(INSTALLS_FILE), contents)
+            .map_err(|it| format!("Failed to update tracked installations: {:?}", it))?;
+    }
+    Ok(referenced)
+}
+
+/// A cached file named after its hash, or a cached API response named after the hash of its url
+struct Entry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime
+}
+
+impl Entry {
+    fn hash(&self) -> String {
+        self.path.file_name().unwrap().to_string_lossy().to_string()
+    }
+}
+
+/// Lists the cached files, or the cached API responses with `metadata`
+fn listEntries(cacheDir: &Path, metadata: bool) -> Result<Vec<Entry>, String> {
+    let dir = cacheDir.join(if metadata { "metadata" } else { "files" });
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut entries: Vec<Entry> = vec![];
+    for path in listFiles(&dir)? {
+        let metadata = fs::metadata(&path).map_err(|it| format!("Failed to read {:?}: {:?}", path, it))?;
+        entries.push(Entry { path, size: metadata.len(), modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH) });
+    }
+    Ok(entries)
+}
+
+fn getCacheDir() -> Result<PathBuf, String> {
+    cacheDir().ok_or("Couldn't find the cache directory, set $XDG_CACHE_HOME".to_string())
+}
+
+/// Prints where the cache is, how big it is and how much of it tracked installations use
+pub fn info() -> Result<(), String> {
+    let cacheDir = getCacheDir()?;
+    let entries = listEntries(&cacheDir, false)?;
+    let responses = listEntries(&cacheDir, true)?;
+    let referenced = getReferenced(&cacheDir)?;
+    let used: Vec<&Entry> = entries.iter().filter(|entry| referenced.contains(&entry.hash())).collect();
+    println!("Cache directory: {}", cacheDir.display());
+    println!("Files: {} ({})", entries.len(), formatSize(entries.iter().map(|entry| entry.size).sum()));
+    println!("Used by tracked installations: {} ({}) across {} installations",
+        used.len(), formatSize(used.iter().map(|entry| entry.size).sum()), readInstalls(&cacheDir).len());
+    println!("API responses: {} ({})", responses.len(), formatSize(responses.iter().map(|entry| entry.size).sum()));
+    Ok(())
+}
+
+/// Removes entries not used within `olderThan`, then the least recently used entries until the cache fits in `maxSize` bytes,
+/// entries referenced by tracked installations are always kept
+pub fn prune(olderThan: Option<Duration>, maxSize: Option<u64>) -> Result<(), String> {
+    let cacheDir = getCacheDir()?;
+    let referenced = getReferenced(&cacheDir)?;
+    let mut entries = listEntries(&cacheDir, false)?;
+    entries.extend(listEntries(&cacheDir, true)?);
+    entries.sort_by_key(|entry| entry.modified);
+    let mut size: u64 = entries.iter().map(|entry| entry.size).sum();
+    let now = SystemTime::now();
+    let mut removed: Vec<Entry> = vec![];
+    for entry in entries {
+        if referenced.contains(&entry.hash()) {
+            continue;
+        }
+        let old = olderThan.is_some_and(|age| now.duration_since(entry.modified).unwrap_or_default() > age);
+        let over = maxSize.is_some_and(|max| size > max);
+        if old || over {
+            size -= entry.size;
+            removed.push(entry);
+        }
+    }
+    removeEntries(&removed)
+}
+
+/// Removes every cached API response and every file not referenced by a tracked installation
+pub fn clear() -> Result<(), String> {
+    let cacheDir = getCacheDir()?;
+    let referenced = getReferenced(&cacheDir)?;
+    let mut removed: Vec<Entry> = listEntries(&cacheDir, false)?.into_iter()
+        .filter(|entry| !referenced.contains(&entry.hash()))
+        .collect();
+    removed.extend(listEntries(&cacheDir, true)?);
+    removeEntries(&removed)
+}
+
+fn removeEntries(entries: &[Entry]) -> Result<(), String> {
+    for entry in entries {
+        fs::remove_file(&entry.path).map_err(|it| format!("Failed to remove {:?}: {:?}", entry.path, it))?;
+        if let Some(parent) = entry.path.parent() {
+            let _ = fs::remove_dir(parent); // Only succeeds once empty
+        }
+    }
+    println!("Removed {} files, reclaimed {}", entries.len(), formatSize(entries.iter().map(|entry| entry.size).sum()));
+    Ok(())
+}
+
+/// Parses an age such as 30d, 12h, 45m, 10s or 2w
+pub fn parseAge(value: &str) -> Result<Duration, String> {
+    let split = value.find(|it: char| !it.is_ascii_digit()).unwrap_or(value.len());
+    let (count, unit) = value.split_at(split);
+    let count: u64 = count.parse().map_err(|_| format!("Invalid age: {value}"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" | "" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid age unit in {value}, use s, m, h, d or w"))
+    };
+    Ok(Duration::from_secs(count * seconds))
+}
+
+/// Parses a size such as 10G, 500M or 1024K, in powers of 1024, or a number of bytes
+pub fn parseSize(value: &str) -> Result<u64, String> {
+    let split = value.find(|it: char| !it.is_ascii_digit() && it != '.').unwrap_or(value.len());
+    let (count, unit) = value.split_at(split);
+    let count: f64 = count.parse().map_err(|_| format!("Invalid size: {value}"))?;
+    let unit = unit.trim_end_matches(['B', 'b']).trim_end_matches('i').to_ascii_uppercase();
+    let exponent = match unit.as_str() {
+        "" => 0,
+        "K" => 1,
+        "M" => 2,
+        "G" => 3,
+        "T" => 4,
+        _ => return Err(format!("Invalid size unit in {value}, use K, M, G or T"))
+    };
+    Ok((count * 1024f64.powi(exponent)) as u64)
+}
+
+pub fn formatSize(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
@@ -0,0 +1,88 @@
+use std::{
+    fs::{create_dir_all, File},
+    io::Read,
+    path::Path,
+    thread
+};
+
+use json::{object, JsonValue};
+use zip::ZipArchive;
+
+use crate::{downloadFiles, extractZip, source::{Curseforge, PackSource}, state};
+
+/// Installs a curseforge modpack zip into a directory named after it, mods are resolved from their project and file ids
+pub fn installZip(path: &Path, threads: usize, curseforge: &Curseforge) -> Result<(), String> {
+    let id = path.file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .ok_or("Invalid zip path")?;
+    let file = File::open(path).map_err(|it| format!("Failed to open {:?}: {:?}", path, it))?;
+    let mut archive = ZipArchive::new(file)
+        .map_err(|it| format!("Failed to parse {:?} as zip: {:?}", path, it))?;
+    let mut buf = String::new();
+    archive.by_name("manifest.json")
+        .map_err(|it| format!("The zip has no manifest.json: {:?}", it))?
+        .read_to_string(&mut buf)
+        .map_err(|it| format!("Failed to read manifest: {:?}", it))?;
+    let manifest = json::parse(&buf).map_err(|it| format!("Manifest is invalid: {:?}", it))?;
+    create_dir_all(format!("./{id}"))
+        .map_err(|it| format!("Failed to create pack directory: {:?}", it))?;
+
+    let mut ids: Vec<(i64, i64)> = vec![];
+    for entry in manifest["files"].members() {
+        let (project, fileId) = match (entry["projectID"].as_i64(), entry["fileID"].as_i64()) {
+            (Some(project), Some(fileId)) => (project, fileId),
+            _ => return Err(format!("Invalid file entry in manifest: {}", entry))
+        };
+        if entry["required"] == false {
+            println!("Skipping optional file {project}/{fileId}");
+            continue;
+        }
+        ids.push((project, fileId));
+    }
+    let mut files = resolveFiles(curseforge, &ids, threads)?;
+    for file in files.iter_mut() {
+        // The manifest doesn't say what kind of file each one is, non jar files are most likely resource packs
+        if !file["name"].to_string().ends_with(".jar") {
+            eprintln!("Warning: {} isn't a jar, placing it in resourcepacks, move it if it's something else such as a shader pack", file["name"]);
+            file["path"] = "./resourcepacks/".into();
+        } else {
+            file["path"] = "./mods/".into();
+        }
+    }
+    let files: Vec<&JsonValue> = files.iter().collect();
+    downloadFiles(&id, &files, threads)?;
+
+    println!("Extracting overrides");
+    let overrides = manifest["overrides"].as_str().unwrap_or("overrides");
+    let file = File::open(path).map_err(|it| format!("Failed to open {:?}: {:?}", path, it))?;
+    extractZip(file, &format!("./{id}/"), Some(overrides), false)?;
+
+    let versionManifest = object! { name: manifest["version"].clone() };
+    let targets = curseforge.getTargets(&id, &versionManifest).ok();
+    state::write(&id, curseforge.name(), &versionManifest, &files, targets.as_ref())?;
+    println!("Installed {} into ./{id}", manifest["name"]);
+    Ok(())
+}
+
+/// Looks up the name, url, size and hash of every file, spread over `threads` threads
+fn resolveFiles(curseforge: &Curseforge, ids: &[(i64, i64)], threads: usize) -> Result<Vec<JsonValue>, String> {
+    let chunkSize = ids.len().div_ceil(threads.max(1)).max(1);
+    thread::scope(|scope| {
+        let workers: Vec<_> = ids.chunks(chunkSize)
+            .map(|chunk| scope.spawn(move || {
+                chunk.iter()
+                    .map(|(project, fileId)| {
+                        let file = curseforge.getFile(*project, *fileId)?;
+                        println!("Resolved {project}/{fileId} to {}", file["name"]);
+                        Ok(file)
+                    })
+                    .collect::<Result<Vec<JsonValue>, String>>()
+            }))
+            .collect();
+        let mut files = vec![];
+        for worker in workers {
+            files.extend(worker.join().map_err(|_| "File resolution panicked".to_string())??);
+        }
+        Ok(files)
+    })
+}
@@ -0,0 +1,162 @@
+use std::{env, fs, path::PathBuf};
+
+use toml::{Table, Value};
+
+use crate::configDir;
+
+/// The public modpacks.ch API
+pub const DEFAULT_API_URL: &str = "https://api.modpacks.ch/public";
+/// The official curseforge API, which needs an API key
+pub const DEFAULT_CURSEFORGE_API_URL: &str = "https://api.curseforge.com/v1";
+
+/// Settings for one project, read from the working directory on top of config.toml
+pub const PROJECT_CONFIG: &str = "modpacks.toml";
+
+/// Settings read from config.toml in the config directory and modpacks.toml in the working directory,
+/// command line flags are applied on top by main
+#[derive(Default)]
+pub struct Config {
+    /// The files the settings were read from, later ones take precedence
+    pub files: Vec<PathBuf>,
+    pub threads: Option<usize>,
+    /// Fail installs with files that have no hash instead of warning
+    pub strict: Option<bool>,
+    /// Java executable used to run modloader installers instead of the one on $PATH or in $JAVA_HOME
+    pub java: Option<String>,
+    /// Base url of the modpacks.ch API, such as a caching mirror of it
+    pub apiUrl: Option<String>,
+    /// Base url of the curseforge API files such as server packs are looked up in
+    pub curseforgeApiUrl: Option<String>,
+    /// Key sent to the curseforge API in the x-api-key header
+    pub curseforgeApiKey: Option<String>,
+    /// How long API responses are used before revalidating them, such as 10m
+    pub metadataTtl: Option<String>,
+    /// Proxy url used instead of the one in $HTTPS_PROXY
+    pub proxy: Option<String>,
+    /// Comma separated hosts reached without the proxy, used instead of $NO_PROXY
+    pub noProxy: Option<String>,
+    /// PEM file with certificates to trust on top of the usual roots
+    pub caBundle: Option<String>,
+    pub connectTimeout: Option<String>,
+    pub readTimeout: Option<String>,
+    pub userAgent: Option<String>,
+    /// Bytes per second all downloads share, such as 5M
+    pub limitRate: Option<String>,
+    /// Base urls files are downloaded from when their own url fails
+    pub mirrors: Vec<String>
+}
+
+impl Config {
+    pub fn load() -> Result<Self, String> {
+        let mut table = Table::new();
+        let mut files = vec![];
+        for path in [configDir().map(|dir| dir.join("config.toml")), Some(PathBuf::from(PROJECT_CONFIG))].into_iter().flatten() {
+            if !path.exists() {
+                continue;
+            }
+            let contents = fs::read_to_string(&path)
+                .map_err(|it| format!("Failed to read {:?}: {:?}", path, it))?;
+            let values: Table = contents.parse()
+                .map_err(|it| format!("Failed to parse {:?}: {}", path, it))?;
+            table.extend(values);
+            files.push(path);
+        }
+        let threads = match table.get("threads") {
+            Some(value) => Some(value.as_integer().and_then(|threads| usize::try_from(threads).ok()).ok_or("threads must be a number")?),
+            None => None
+        };
+        let strict = match table.get("strict") {
+            Some(value) => Some(value.as_bool().ok_or("strict must be true or false")?),
+            None => None
+        };
+        Ok(Self {
+            files,
+            threads,
+            strict,
+            java: getString(&table, "java")?,
+            apiUrl: getString(&table, "api-url")?,
+            curseforgeApiUrl: getString(&table, "curseforge-api-url")?,
+            curseforgeApiKey: getString(&table, "curseforge-api-key")?,
+            metadataTtl: getString(&table, "metadata-ttl")?,
+            proxy: getString(&table, "proxy")?,
+            noProxy: getString(&table, "no-proxy")?,
+            caBundle: getString(&table, "ca-bundle")?,
+            connectTimeout: getString(&table, "connect-timeout")?,
+            readTimeout: getString(&table, "read-timeout")?,
+            userAgent: getString(&table, "user-agent")?,
+            limitRate: getString(&table, "limit-rate")?,
+            mirrors: getStrings(&table, "mirrors")?
+        })
+    }
+
+    /// The curseforge API base url, set in the config file or the official API
+    pub fn resolveCurseforgeApiUrl(&self) -> String {
+        self.curseforgeApiUrl.as_deref().unwrap_or(DEFAULT_CURSEFORGE_API_URL).trim_end_matches('/').to_string()
+    }
+
+    /// The curseforge API key, $CURSEFORGE_API_KEY takes precedence over the config file
+    pub fn resolveCurseforgeApiKey(&self) -> Option<String> {
+        env::var("CURSEFORGE_API_KEY").ok()
+            .or_else(|| self.curseforgeApiKey.clone())
+            .filter(|key| !key.is_empty())
+    }
+
+    /// The API base url, `--api-url` takes precedence over $MODPACKS_API_URL, which takes precedence over the config file
+    pub fn resolveApiUrl(&self, flag: Option<String>) -> String {
+        flag.or_else(|| env::var("MODPACKS_API_URL").ok())
+            .or_else(|| self.apiUrl.clone())
+            .unwrap_or_else(|| DEFAULT_API_URL.to_string())
+            .trim_end_matches('/')
+            .to_string()
+    }
+
+    /// The effective settings in the format of config.toml, preceded by the files they were read from
+    pub fn show(&self) -> String {
+        let mut table = Table::new();
+        let strings = [
+            ("api-url", &self.apiUrl), ("curseforge-api-url", &self.curseforgeApiUrl), ("metadata-ttl", &self.metadataTtl), ("proxy", &self.proxy), ("no-proxy", &self.noProxy),
+            ("ca-bundle", &self.caBundle), ("connect-timeout", &self.connectTimeout), ("read-timeout", &self.readTimeout),
+            ("user-agent", &self.userAgent), ("limit-rate", &self.limitRate), ("java", &self.java)
+        ];
+        if let Some(threads) = self.threads {
+            table.insert("threads".to_string(), Value::Integer(threads as i64));
+        }
+        if let Some(strict) = self.strict {
+            table.insert("strict".to_string(), Value::Boolean(strict));
+        }
+        for (key, value) in strings {
+            if let Some(value) = value {
+                table.insert(key.to_string(), Value::String(value.clone()));
+            }
+        }
+        // Kept out of the output, which gets pasted into bug reports
+        if self.curseforgeApiKey.is_some() {
+            table.insert("curseforge-api-key".to_string(), Value::String("<hidden>".to_string()));
+        }
+        if !self.mirrors.is_empty() {
+            table.insert("mirrors".to_string(), Value::Array(self.mirrors.iter().cloned().map(Value::String).collect()));
+        }
+        let files = if self.files.is_empty() {
+            "# No config files found\n".to_string()
+        } else {
+            self.files.iter().map(|path| format!("# Read from {}\n", path.display())).collect()
+        };
+        files + &table.to_string()
+    }
+}
+
+fn getStrings(table: &Table, key: &str) -> Result<Vec<String>, String> {
+    match table.get(key) {
+        Some(value) => value.as_array()
+            .and_then(|values| values.iter().map(|value| value.as_str().map(str::to_string)).collect())
+            .ok_or(format!("{key} must be an array of strings")),
+        None => Ok(vec![])
+    }
+}
+
+fn getString(table: &Table, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        Some(value) => Ok(Some(value.as_str().ok_or(format!("{key} must be a string"))?.to_string())),
+        None => Ok(None)
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf}
+};
+
+use json::{object, JsonValue};
+use sha1::{Digest, Sha1};
+use sha2::Sha512;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::state::{self, STATE_FILE};
+
+/// Files at the root of an installation that belong to the installer or launcher rather than the pack
+const IGNORED: [&str; 5] = [STATE_FILE, "overrides.zip", "manifest.json", "modlist.html", "server-pack.zip"];
+
+/// Format of an exported pack
+#[derive(PartialEq, Clone, Copy)]
+pub enum Format {
+    /// A curseforge modpack zip
+    Curseforge,
+    /// A modrinth .mrpack
+    Modrinth
+}
+
+impl Format {
+    pub fn extension(&self) -> &str {
+        match self {
+            Self::Curseforge => "zip",
+            Self::Modrinth => "mrpack"
+        }
+    }
+}
+
+/// Exports the installation in `dir`, files that are still the ones that were downloaded are referenced
+/// by their curseforge ids or download url and everything else is added as an override
+pub fn export(dir: &Path, output: &Path, format: Format) -> Result<(), String> {
+    let state = state::read(dir)?;
+    let minecraft = state["minecraft"].as_str().ok_or("The install state doesn't record the minecraft version")?;
+    let gameDir = getGameDir(dir);
+
+    let mut archive = ZipWriter::new(File::create(output)
+        .map_err(|it| format!("Failed to create {:?}: {:?}", output, it))?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut files = JsonValue::new_array();
+    for path in listFiles(&gameDir)? {
+        let relative = path.strip_prefix(&gameDir).unwrap().to_string_lossy().replace('\\', "/");
+        if IGNORED.contains(&relative.as_str()) {
+            continue;
+        }
+        let raw = fs::read(&path).map_err(|it| format!("Failed to read {:?}: {:?}", path, it))?;
+        let recorded = findUnchanged(&state, &relative, &raw).filter(|recorded| match format {
+            Format::Curseforge => recorded["curseforge"].is_object(),
+            Format::Modrinth => !recorded["url"].is_empty()
+        });
+        match recorded {
+            Some(recorded) if format == Format::Curseforge => {
+                files.push(object! {
+                    projectID: recorded["curseforge"]["project"].clone(),
+                    fileID: recorded["curseforge"]["file"].clone(),
+                    required: true
+                }).unwrap();
+            }
+            Some(recorded) => {
+                let mut hasher = Sha512::new();
+                hasher.update(&raw);
+                files.push(object! {
+                    path: relative.as_str(),
+                    hashes: object! { sha1: recorded["sha1"].clone(), sha512: hex::encode(hasher.finalize()) },
+                    env: object! {
+                        client: if recorded["serveronly"] == true { "unsupported" } else { "required" },
+                        server: if recorded["clientonly"] == true { "unsupported" } else { "required" }
+                    },
+                    downloads: vec![recorded["url"].clone()],
+                    fileSize: raw.len()
+                }).unwrap();
+            }
+            None => {
+                println!("Adding override: {}", relative);
+                archive.start_file(format!("overrides/{relative}"), options)
+                    .and_then(|_| archive.write_all(&raw).map_err(Into::into))
+                    .map_err(|it| format!("Failed to add {relative} to the archive: {:?}", it))?;
+            }
+        }
+    }
+
+    let name = dir.canonicalize()
+        .ok()
+        .and_then(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+        .unwrap_or_else(|| state["pack"].to_string());
+    let (manifestName, manifest) = match format {
+        Format::Curseforge => ("manifest.json", getCurseforgeManifest(&state, minecraft, name, files)),
+        Format::Modrinth => ("modrinth.index.json", getModrinthIndex(&state, minecraft, name, files)?)
+    };
+    archive.start_file(manifestName, options)
+        .and_then(|_| archive.write_all(json::stringify_pretty(manifest, 4).as_bytes()).map_err(Into::into))
+        .and_then(|_| archive.finish().map(drop))
+        .map_err(|it| format!("Failed to write the archive: {:?}", it))?;
+    println!("Exported to {:?}", output);
+    Ok(())
+}
+
+fn getCurseforgeManifest(state: &JsonValue, minecraft: &str, name: String, files: JsonValue) -> JsonValue {
+    let mut modLoaders = JsonValue::new_array();
+    if state["loader"].is_object() {
+        modLoaders.push(object! { id: format!("{}-{}", state["loader"]["name"], state["loader"]["version"]), primary: true }).unwrap();
+    }
+    object! {
+        minecraft: object! { version: minecraft, modLoaders: modLoaders },
+        manifestType: "minecraftModpack",
+        manifestVersion: 1,
+        name: name,
+        version: state["versionName"].to_string(),
+        author: "",
+        files: files,
+        overrides: "overrides"
+    }
+}
+
+fn getModrinthIndex(state: &JsonValue, minecraft: &str, name: String, files: JsonValue) -> Result<JsonValue, String> {
+    let mut dependencies = object! { minecraft: minecraft };
+    if state["loader"].is_object() {
+        let loader = match state["loader"]["name"].as_str() {
+            Some("forge") => "forge",
+            Some("neoforge") => "neoforge",
+            Some("fabric") => "fabric-loader",
+            Some("quilt") => "quilt-loader",
+            other => return Err(format!("Modrinth packs don't support the {:?} modloader", other))
+        };
+        dependencies[loader] = state["loader"]["version"].clone();
+    }
+    Ok(object! {
+        formatVersion: 1,
+        game: "minecraft",
+        versionId: state["versionName"].to_string(),
+        name: name,
+        files: files,
+        dependencies: dependencies
+    })
+}
+
+/// The directory holding the game files, which is .minecraft for launcher instances
+pub fn getGameDir(dir: &Path) -> PathBuf {
+    let instance = dir.join(".minecraft");
+    if instance.is_dir() {
+        instance
+    } else {
+        dir.to_path_buf()
+    }
+}
+
+/// The recorded file installed at `relative`, if its contents are still what was downloaded
+fn findUnchanged<'a>(state: &'a JsonValue, relative: &str, raw: &[u8]) -> Option<&'a JsonValue> {
+    let recorded = state["files"].members().find(|file| file["path"] == relative)?;
+    let mut hasher = Sha1::new();
+    hasher.update(raw);
+    if recorded["sha1"] == hex::encode(hasher.finalize()).as_str() {
+        Some(recorded)
+    } else {
+        None
+    }
+}
+
+/// Lists every file below `dir`
+pub fn listFiles(dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let mut files: Vec<PathBuf> = vec![];
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries = fs::read_dir(&current)
+            .map_err(|it| format!("Failed to list {:?}: {:?}", current, it))?;
+        for entry in entries {
+            let path = entry.map_err(|it: io::Error| format!("Failed to list {:?}: {:?}", current, it))?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
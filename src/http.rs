@@ -0,0 +1,185 @@
+use std::{
+    env,
+    fs,
+    io::{self, Read},
+    sync::{atomic::{AtomicU64, Ordering}, Arc, Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant}
+};
+
+use rustls::{pki_types::{pem::PemObject, CertificateDer}, ClientConfig, RootCertStore};
+use ureq::{Agent, AgentBuilder, Proxy, Request, Response};
+
+use crate::{cache::parseAge, config::Config};
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+static AGENTS: OnceLock<Agents> = OnceLock::new();
+
+/// Bytes per second shared by every download, 0 when unlimited
+static RATE_LIMIT: AtomicU64 = AtomicU64::new(0);
+/// When the bytes read so far are allowed to have been read at the rate limit
+static RATE_NEXT: Mutex<Option<Instant>> = Mutex::new(None);
+/// How far downloads may run ahead of the rate limit, so short bursts don't stall
+const RATE_BURST: Duration = Duration::from_millis(250);
+/// Reads are split into chunks of at most this size so the limit is applied smoothly
+const RATE_CHUNK: usize = 16 * 1024;
+
+/// Every request goes through these, so they share connections and settings
+struct Agents {
+    direct: Agent,
+    /// Used for hosts not matched by `noProxy` when a proxy is configured
+    proxied: Option<Agent>,
+    noProxy: Vec<String>
+}
+
+/// Builds the agents from the config file and the proxy environment variables, requests made before use default settings
+pub fn configure(config: &Config) -> Result<(), String> {
+    let agents = buildAgents(config)?;
+    AGENTS.set(agents).map_err(|_| "HTTP is already configured".to_string())
+}
+
+/// Starts a GET request to `url`
+pub fn get(url: &str) -> Request {
+    let agents = getAgents();
+    match &agents.proxied {
+        Some(proxied) if !agents.bypassesProxy(url) => proxied.get(url),
+        _ => agents.direct.get(url)
+    }
+}
+
+fn getAgents() -> &'static Agents {
+    AGENTS.get_or_init(|| buildAgents(&Config::default()).expect("Failed to set up HTTP"))
+}
+
+impl Agents {
+    /// Whether the host of `url` is listed in NO_PROXY, either exactly or as a subdomain of an entry
+    fn bypassesProxy(&self, url: &str) -> bool {
+        let host = getHost(url);
+        self.noProxy.iter().any(|entry| {
+            let entry = entry.trim_start_matches("*.").trim_start_matches('.');
+            entry == "*" || host == entry || host.ends_with(&format!(".{entry}"))
+        })
+    }
+}
+
+fn buildAgents(config: &Config) -> Result<Agents, String> {
+    let connectTimeout = match &config.connectTimeout {
+        Some(timeout) => parseAge(timeout)?,
+        None => DEFAULT_CONNECT_TIMEOUT
+    };
+    let readTimeout = match &config.readTimeout {
+        Some(timeout) => parseAge(timeout)?,
+        None => DEFAULT_READ_TIMEOUT
+    };
+    let tlsConfig = match &config.caBundle {
+        Some(path) => Some(getTlsConfig(path)?),
+        None => None
+    };
+    let build = |proxy: Option<&Proxy>| {
+        let mut builder = AgentBuilder::new()
+            .timeout_connect(connectTimeout)
+            .timeout_read(readTimeout)
+            .try_proxy_from_env(false);
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        if let Some(userAgent) = &config.userAgent {
+            builder = builder.user_agent(userAgent);
+        }
+        if let Some(tlsConfig) = &tlsConfig {
+            builder = builder.tls_config(Arc::clone(tlsConfig));
+        }
+        builder.build()
+    };
+
+    let proxy = config.proxy.clone()
+        .or_else(|| getEnv(&["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"]))
+        .filter(|proxy| !proxy.is_empty())
+        .map(|proxy| Proxy::new(&proxy).map_err(|it| format!("Invalid proxy {proxy}: {:?}", it)))
+        .transpose()?;
+    let noProxy = config.noProxy.clone()
+        .or_else(|| getEnv(&["NO_PROXY", "no_proxy"]))
+        .map(|list| list.split(',').map(|entry| entry.trim().to_ascii_lowercase()).filter(|entry| !entry.is_empty()).collect())
+        .unwrap_or_default();
+    Ok(Agents {
+        direct: build(None),
+        proxied: proxy.as_ref().map(|proxy| build(Some(proxy))),
+        noProxy
+    })
+}
+
+/// Trusts the certificates in the PEM bundle at `path` on top of the usual roots, for networks with a private root CA
+fn getTlsConfig(path: &str) -> Result<Arc<ClientConfig>, String> {
+    let contents = fs::read(path).map_err(|it| format!("Failed to read CA bundle {path}: {:?}", it))?;
+    let mut roots = RootCertStore { roots: webpki_roots::TLS_SERVER_ROOTS.to_vec() };
+    let mut added = 0;
+    for cert in CertificateDer::pem_slice_iter(&contents) {
+        let cert = cert.map_err(|it| format!("Invalid certificate in {path}: {:?}", it))?;
+        roots.add(cert).map_err(|it| format!("Invalid certificate in {path}: {:?}", it))?;
+        added += 1;
+    }
+    if added == 0 {
+        return Err(format!("{path} has no certificates"));
+    }
+    Ok(Arc::new(ClientConfig::builder().with_root_certificates(roots).with_no_client_auth()))
+}
+
+fn getEnv(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| env::var(name).ok())
+}
+
+/// The lowercase host of `url` without credentials or port
+pub fn getHost(url: &str) -> String {
+    let authority = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let authority = authority.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority.rsplit_once('@').map_or(authority, |(_, host)| host);
+    let host = match host.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default()
+    };
+    host.to_ascii_lowercase()
+}
+
+/// Limits the combined speed of every download to `bytesPerSecond`
+pub fn limitRate(bytesPerSecond: u64) {
+    RATE_LIMIT.store(bytesPerSecond, Ordering::Relaxed);
+}
+
+/// The body of a download, throttled when a rate limit is set
+pub fn reader(resp: Response) -> impl Read {
+    Throttled { inner: resp.into_reader() }
+}
+
+struct Throttled<R: Read> {
+    inner: R
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let limit = RATE_LIMIT.load(Ordering::Relaxed);
+        if limit == 0 {
+            return self.inner.read(buf);
+        }
+        let len = buf.len().min(RATE_CHUNK);
+        let read = self.inner.read(&mut buf[..len])?;
+        throttle(read as u64, limit);
+        Ok(read)
+    }
+}
+
+/// Sleeps until `bytes` more fit in the rate limit, every reader pushes back the same deadline so the limit is shared
+fn throttle(bytes: u64, limit: u64) {
+    let now = Instant::now();
+    let deadline = {
+        let mut next = RATE_NEXT.lock().unwrap();
+        let start = next.filter(|next| *next > now).unwrap_or(now);
+        let deadline = start + Duration::from_secs_f64(bytes as f64 / limit as f64);
+        *next = Some(deadline);
+        deadline
+    };
+    if let Some(wait) = deadline.checked_duration_since(now + RATE_BURST) {
+        thread::sleep(wait);
+    }
+}
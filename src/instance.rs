@@ -0,0 +1,151 @@
+use std::{
+    fs::{self, create_dir_all, File},
+    io::{self, Read, Write},
+    path::Path
+};
+
+use json::{object, JsonValue};
+
+use crate::{http, state::STATE_FILE, Targets};
+
+/// Launcher whose instance format a downloaded pack is wrapped in
+#[derive(PartialEq, Clone, Copy)]
+pub enum Launcher {
+    Prism,
+    MultiMC
+}
+
+impl Launcher {
+    pub fn fromName(name: &str) -> Option<Self> {
+        match name {
+            "prism" => Some(Self::Prism),
+            "multimc" => Some(Self::MultiMC),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Prism => "Prism Launcher",
+            Self::MultiMC => "MultiMC"
+        }
+    }
+}
+
+/// Turns the pack downloaded to `./{id}` into an instance by moving its files into `.minecraft`
+/// and writing the instance configuration, `name` is shown in the launcher and `icon` is the url of the pack art
+pub fn makeInstance(id: &str, launcher: Launcher, name: &str, targets: &Targets, icon: Option<String>) -> Result<(), String> {
+    let components = getComponents(launcher, targets)?;
+    let dir = format!("./{id}");
+    let gameDir = format!("{dir}/.minecraft");
+    create_dir_all(&gameDir)
+        .map_err(|it| format!("Failed to create .minecraft: {:?}", it))?;
+    let iconKey = format!("modpacks_{id}");
+    // Left in place so downloading into an existing instance again only moves the new pack files
+    let instanceFiles = [".minecraft", STATE_FILE, "instance.cfg", "mmc-pack.json", &format!("{iconKey}.png")];
+    let entries = fs::read_dir(&dir)
+        .map_err(|it| format!("Failed to list pack files: {:?}", it))?;
+    for entry in entries.flatten() {
+        if instanceFiles.iter().any(|name| entry.file_name() == **name) {
+            continue;
+        }
+        moveMerged(&entry.path(), &Path::new(&gameDir).join(entry.file_name()))
+            .map_err(|it| format!("Failed to move {:?} into .minecraft: {:?}", entry.file_name(), it))?;
+    }
+
+    let hasIcon = match icon {
+        Some(url) => match downloadIcon(&url, &format!("{dir}/{iconKey}.png")) {
+            Ok(()) => true,
+            Err(error) => {
+                eprintln!("Failed to download pack icon: {}", error);
+                false
+            }
+        },
+        None => false
+    };
+
+    let mut config = String::new();
+    if launcher == Launcher::Prism {
+        config += "[General]\n";
+    }
+    config += "InstanceType=OneSix\n";
+    config += &format!("name={name}\n");
+    config += &format!("iconKey={}\n", if hasIcon { &iconKey } else { "default" });
+    writeFile(&format!("{dir}/instance.cfg"), &config)?;
+
+    let pack = object! {
+        components: components,
+        formatVersion: 1
+    };
+    writeFile(&format!("{dir}/mmc-pack.json"), &json::stringify_pretty(pack, 4))?;
+    println!("Created {} instance, import {dir} from the launcher", launcher.name());
+    Ok(())
+}
+
+/// The components of mmc-pack.json for the minecraft version and modloader of the pack
+fn getComponents(launcher: Launcher, targets: &Targets) -> Result<JsonValue, String> {
+    let mut components = JsonValue::new_array();
+    components.push(object! { uid: "net.minecraft", version: targets.minecraft.clone(), important: true }).unwrap();
+    if let Some((name, version)) = &targets.loader {
+        match name.as_str() {
+            "forge" => {
+                components.push(object! { uid: "net.minecraftforge", version: version.clone() }).unwrap();
+            }
+            "neoforge" if launcher == Launcher::Prism => {
+                components.push(object! { uid: "net.neoforged", version: version.clone() }).unwrap();
+            }
+            "fabric" => {
+                components.push(object! { uid: "net.fabricmc.intermediary", version: targets.minecraft.clone(), dependencyOnly: true }).unwrap();
+                components.push(object! { uid: "net.fabricmc.fabric-loader", version: version.clone() }).unwrap();
+            }
+            "quilt" => {
+                components.push(object! { uid: "net.fabricmc.intermediary", version: targets.minecraft.clone(), dependencyOnly: true }).unwrap();
+                components.push(object! { uid: "org.quiltmc.quilt-loader", version: version.clone() }).unwrap();
+            }
+            other => {
+                return Err(format!("{} doesn't support the {} modloader", launcher.name(), other));
+            }
+        }
+    }
+    Ok(components)
+}
+
+/// Moves `from` to `to`, merging into a directory that already exists there and replacing the files in it
+fn moveMerged(from: &Path, to: &Path) -> io::Result<()> {
+    if from.is_dir() && to.is_dir() {
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            moveMerged(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        return fs::remove_dir(from);
+    }
+    if to.is_dir() {
+        fs::remove_dir_all(to)?;
+    }
+    fs::rename(from, to)?;
+    // Renaming does nothing when both are hard links to the same cached file
+    if from.exists() {
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Saves the icon next to instance.cfg, launchers import icons named after the icon key from there
+fn downloadIcon(url: &str, path: &str) -> Result<(), String> {
+    let resp = http::get(url)
+        .call()
+        .map_err(|it| format!("Failed to GET icon: {:?}", it))?;
+    let mut raw: Vec<u8> = vec![];
+    http::reader(resp)
+        .read_to_end(&mut raw)
+        .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
+    File::create(path)
+        .and_then(|mut file| file.write_all(&raw))
+        .map_err(|it| format!("Failed to save icon: {:?}", it))
+}
+
+fn writeFile(path: &str, contents: &str) -> Result<(), String> {
+    File::create(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .map_err(|it| format!("Failed to write {path}: {:?}", it))
+}
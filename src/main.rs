@@ -1,43 +1,133 @@
-#![allow(non_snake_case)]
+#![allow(non_snake_case, clippy::upper_case_acronyms)]
 
 use std::{
     env, 
     collections::HashMap, 
-    io::{Read, Write, self}, 
-    fs::{create_dir_all, File}, 
-    sync::{mpsc::{self, Sender}, Arc, Mutex}, 
+    io::{Read, Seek, Write, self}, 
+    fs::{self, create_dir_all, File}, 
+    sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Sender}, Arc, Mutex, OnceLock}, 
     path::PathBuf, 
-    process::Command, 
+    process::{self, Child, Command, Stdio}, 
     time::Duration
 };
 
 #[cfg(not(windows))]
-use std::{fs::{Permissions, self}, os::unix::prelude::PermissionsExt};
+use std::{fs::Permissions, os::unix::prelude::PermissionsExt};
 
 use json::JsonValue;
 use roxmltree::Document;
 use sha1::{Sha1, Digest};
+use sha2::{Sha256, Sha512};
 use threadpool::ThreadPool;
-use ureq::Response;
 use zip::ZipArchive;
 
+use config::Config;
+use export::Format;
+use instance::Launcher;
+use server::ServerOptions;
+use source::{Curseforge, PackSource, FTB};
+
+mod cache;
+mod cfzip;
+mod config;
+mod export;
+mod http;
+mod instance;
+mod mrpack;
+mod server;
+mod source;
+mod state;
+
 const USER_AGENT: &str = "modpacklauncher/202207271710-0f9644f5fc-release Mozilla/5.0 (LINUX) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/57.0.2987.138 Safari/537.36 Vivaldi/1.8.770.56";
 
 fn main() {
-    let mut args = env::args();
-    let firstArg = args.nth(1).expect("Invalid usage (consider \"help\")");
-    let mut threadCount: usize = 1;
-    let mut skipped = false;
-    if firstArg == "--threads" {
-        threadCount = args.next().expect("Invalid usage").parse::<usize>().expect("--threads must be a number");
-    } else {
-        skipped = true;
+    let mut args = env::args().skip(1).peekable();
+    let mut threads: Option<usize> = None;
+    let mut apiUrl: Option<String> = None;
+    let mut limitRate: Option<String> = None;
+    let mut mirrors: Vec<String> = vec![];
+    let mut strict = false;
+    let mut java: Option<String> = None;
+    loop {
+        match args.peek().map(String::as_str) {
+            Some("--threads") => {
+                args.next();
+                threads = Some(args.next().expect("Invalid usage").parse::<usize>().expect("--threads must be a number"));
+            }
+            Some("--no-cache") => {
+                args.next();
+                cache::disable();
+            }
+            Some("--refresh") => {
+                args.next();
+                cache::refresh();
+            }
+            Some("--limit-rate") => {
+                args.next();
+                limitRate = Some(args.next().expect("--limit-rate requires a value"));
+            }
+            Some("--strict") => {
+                args.next();
+                strict = true;
+            }
+            Some("--mirror") => {
+                args.next();
+                mirrors.push(args.next().expect("--mirror requires a value"));
+            }
+            Some("--java") => {
+                args.next();
+                java = Some(args.next().expect("--java requires a value"));
+            }
+            Some("--api-url") => {
+                args.next();
+                apiUrl = Some(args.next().expect("--api-url requires a value"));
+            }
+            _ => break
+        }
+    }
+    let command = args.next().expect("Invalid usage (consider \"help\")");
+    if command == "help" {
+        println!("{}", USAGE);
+        return;
+    }
+
+    // Flags override the config files
+    let mut config = Config::load().unwrap_or_else(|error| exitWithError(&error));
+    config.threads = threads.or(config.threads);
+    config.limitRate = limitRate.or(config.limitRate);
+    config.java = java.or(config.java);
+    if strict {
+        config.strict = Some(true);
+    }
+    mirrors.append(&mut config.mirrors);
+    config.mirrors = mirrors;
+    config.apiUrl = Some(config.resolveApiUrl(apiUrl));
+    // Shown before the settings are applied, so settings that fail to apply can still be inspected
+    if command == "config" {
+        match args.next().expect("Invalid usage").as_str() {
+            "show" => {
+                print!("{}", config.show());
+                if let Err(error) = applyConfig(&config) {
+                    exitWithError(&format!("Invalid config: {error}"));
+                }
+            }
+            _ => eprintln!("Invalid usage")
+        }
+        return;
+    }
+    if let Err(error) = applyConfig(&config) {
+        exitWithError(&format!("Invalid config: {error}"));
     }
-    match if skipped { firstArg } else { args.next().expect("Invalid usage (consider \"help\")") }.as_str() {
+
+    let threadCount = config.threads.unwrap_or(1);
+    let apiUrl = config.apiUrl.clone().unwrap_or_default();
+    let ftb = FTB::new(&apiUrl);
+    let curseforge = Curseforge::new(&apiUrl, &config.resolveCurseforgeApiUrl(), config.resolveCurseforgeApiKey());
+    match command.as_str() {
         "ftb" => {
             match args.next().expect("Invalid usage").as_str() {
                 "recent" => {
-                    match getPopular() {
+                    match getPopular(&ftb) {
                         Ok(packs) => {
                             println!("Recent modpacks:");
                             for (pack, info) in packs {
@@ -50,7 +140,7 @@ fn main() {
                     }
                 }
                 "featured" => {
-                    match getFeatured() {
+                    match getFeatured(&ftb) {
                         Ok(packs) => {
                             println!("Featured modpacks:");
                             for (pack, info) in packs {
@@ -63,7 +153,7 @@ fn main() {
                     }
                 }
                 "played" => {
-                    match getPlayed() {
+                    match getPlayed(&ftb) {
                         Ok(packs) => {
                             println!("Most played modpacks:");
                             for (pack, info) in packs {
@@ -76,7 +166,7 @@ fn main() {
                     }
                 }
                 "installed" => {
-                    match getInstalled() {
+                    match getInstalled(&ftb) {
                         Ok(packs) => {
                             println!("Most installed modpacks:");
                             for (pack, info) in packs {
@@ -90,7 +180,7 @@ fn main() {
                 }
                 "search" => {
                     let term = args.next().expect("Invalid usage");
-                    match searchFTB(term) {
+                    match searchPacks(&ftb, &term) {
                         Ok(packs) => {
                             println!("Search results:");
                             for (pack, info) in packs {
@@ -105,12 +195,27 @@ fn main() {
                 "download" => {
                     let pack = args.next().expect("Invalid usage");
                     let version = args.next().expect("Invalid usage");
-                    downloadPack(&pack, version, PackType::FTB, threadCount).expect("Failed to download FTB modpack");
+                    let launcher = takeLauncher(args.collect());
+                    downloadClient(&pack, version, &ftb, threadCount, launcher).expect("Failed to download FTB modpack");
                 }
                 "server" => {
                     let pack = args.next().expect("Invalid usage");
                     let version = args.next().expect("Invalid usage");
-                    downloadFTBServer(pack, version).expect("Failed to install server");
+                    let mut flags: Vec<String> = args.collect();
+                    let installer = takeFlag(&mut flags, "--installer");
+                    let platform = takeOption(&mut flags, "--platform")
+                        .map(|name| Platform::fromName(&name).unwrap_or_else(|| panic!("--platform must be one of {}", Platform::NAMES)));
+                    let options = ServerOptions::fromFlags(&mut flags);
+                    checkNoFlagsLeft(&flags);
+                    let result = if installer || platform.is_some() {
+                        match platform.or_else(Platform::host) {
+                            Some(platform) => runFTBServerInstaller(&ftb, pack, version, platform, &options),
+                            None => Err("The FTB server installer is not available for this platform, pass --platform or omit --installer to install natively".to_string())
+                        }
+                    } else {
+                        downloadFTBServer(&ftb, pack, version, threadCount, &options)
+                    };
+                    result.expect("Failed to install server");
                 }
                 _ => {
                     eprintln!("Invalid usage")
@@ -121,7 +226,7 @@ fn main() {
             match args.next().expect("Invalid usage").as_str() {
                 "search" => {
                     let term = args.next().expect("Invalid usage");
-                    match searchCF(term) {
+                    match searchPacks(&curseforge, &term) {
                         Ok(packs) => {
                             println!("Search results:");
                             for (pack, info) in packs {
@@ -136,20 +241,140 @@ fn main() {
                 "download" => {
                     let pack = args.next().expect("Invalid usage");
                     let version = args.next().expect("Invalid usage");
-                    downloadPack(&pack, version, PackType::CF, threadCount).expect("Failed to download curseforge modpack");
+                    let launcher = takeLauncher(args.collect());
+                    downloadClient(&pack, version, &curseforge, threadCount, launcher).expect("Failed to download curseforge modpack");
                 }
                 "server" => {
                     let pack = args.next().expect("Invalid usage");
                     let version = args.next().expect("Invalid usage");
-                    downloadCFServer(pack, version, threadCount).expect("Failed to install server");
+                    let mut flags: Vec<String> = args.collect();
+                    let denylist = match takeOption(&mut flags, "--client-denylist") {
+                        Some(path) => Denylist::load(&PathBuf::from(path)),
+                        None => Denylist::loadDefault()
+                    }.expect("Failed to load client only denylist");
+                    let serverFiles = match (takeFlag(&mut flags, "--server-pack"), takeFlag(&mut flags, "--client-files")) {
+                        (true, true) => panic!("--server-pack and --client-files are mutually exclusive"),
+                        (true, false) => ServerFiles::ServerPack,
+                        (false, true) => ServerFiles::ClientFiles,
+                        (false, false) => ServerFiles::Auto
+                    };
+                    let options = ServerOptions::fromFlags(&mut flags);
+                    checkNoFlagsLeft(&flags);
+                    downloadCFServer(&curseforge, pack, version, threadCount, &denylist, serverFiles, &options).expect("Failed to install server");
+                }
+                "install-zip" => {
+                    let path = PathBuf::from(args.next().expect("Invalid usage"));
+                    checkNoFlagsLeft(&args.collect::<Vec<String>>());
+                    cfzip::installZip(&path, threadCount, &curseforge).expect("Failed to install curseforge modpack zip");
+                }
+                _ => {
+                    eprintln!("Invalid usage");
+                }
+            }
+        }
+        "mrpack" => {
+            match args.next().expect("Invalid usage").as_str() {
+                "install" => {
+                    let source = args.next().expect("Invalid usage");
+                    let mut flags: Vec<String> = args.collect();
+                    let options = takeFlag(&mut flags, "--server").then(|| ServerOptions::fromFlags(&mut flags));
+                    checkNoFlagsLeft(&flags);
+                    mrpack::install(&source, threadCount, options.as_ref()).expect("Failed to install modrinth pack");
+                }
+                _ => {
+                    eprintln!("Invalid usage");
+                }
+            }
+        }
+        "cache" => {
+            match args.next().expect("Invalid usage").as_str() {
+                "info" => {
+                    cache::info().expect("Failed to read cache");
+                }
+                "prune" => {
+                    let mut flags: Vec<String> = args.collect();
+                    let olderThan = takeOption(&mut flags, "--older-than").map(|age| cache::parseAge(&age).expect("Invalid usage"));
+                    let maxSize = takeOption(&mut flags, "--max-size").map(|size| cache::parseSize(&size).expect("Invalid usage"));
+                    checkNoFlagsLeft(&flags);
+                    if olderThan.is_none() && maxSize.is_none() {
+                        panic!("cache prune needs --older-than or --max-size");
+                    }
+                    cache::prune(olderThan, maxSize).expect("Failed to prune cache");
+                }
+                "clear" => {
+                    checkNoFlagsLeft(&args.collect::<Vec<String>>());
+                    cache::clear().expect("Failed to clear cache");
                 }
                 _ => {
                     eprintln!("Invalid usage");
                 }
             }
         }
-        "help" => {
-            let usage = "Usage (optional: []): modpacks-rs [--threads n] (ftb|cf) verb\n\
+        "export" => {
+            let dir = PathBuf::from(args.next().expect("Invalid usage"));
+            let mut flags: Vec<String> = args.collect();
+            let format = if takeFlag(&mut flags, "--mrpack") { Format::Modrinth } else { Format::Curseforge };
+            let output = takeOption(&mut flags, "--output").map(PathBuf::from);
+            checkNoFlagsLeft(&flags);
+            let output = output.unwrap_or_else(|| {
+                let name = dir.canonicalize().ok().and_then(|path| path.file_name().map(|name| name.to_os_string()));
+                PathBuf::from(name.expect("Invalid directory")).with_extension(format.extension())
+            });
+            export::export(&dir, &output, format).expect("Failed to export modpack");
+        }
+        _ => {
+            eprintln!("Invalid usage (consider \"help\")");
+        }
+    }
+}
+
+/// Sets up HTTP, the cache and downloads from the effective settings
+fn applyConfig(config: &Config) -> Result<(), String> {
+    http::configure(config)?;
+    if let Some(rate) = &config.limitRate {
+        http::limitRate(cache::parseSize(rate).map_err(|it| format!("limit-rate must be a size such as 5M: {it}"))?);
+    }
+    if let Some(ttl) = &config.metadataTtl {
+        cache::setMetadataTtl(cache::parseAge(ttl).map_err(|it| format!("metadata-ttl must be an age such as 10m: {it}"))?);
+    }
+    STRICT.store(config.strict.unwrap_or(false), Ordering::Relaxed);
+    let _ = MIRRORS.set(config.mirrors.clone());
+    if let Some(java) = &config.java {
+        let _ = JAVA.set(java.clone());
+    }
+    Ok(())
+}
+
+/// Reports an error that isn't a bug and exits, such as an invalid config file
+fn exitWithError(error: &str) -> ! {
+    eprintln!("{error}");
+    process::exit(1)
+}
+
+const USAGE: &str = "Usage (optional: []): modpacks-rs [--threads n] [--api-url url] [--no-cache] [--refresh] [--limit-rate rate] [--mirror url]... [--strict] [--java path] (ftb|cf|mrpack|export|cache|config) verb\n\
+--api-url sets the base url of the modpacks.ch API (default https://api.modpacks.ch/public), such as a caching mirror,
+    it can also be set with $MODPACKS_API_URL or api-url in config.toml inside the config directory,
+    curseforge-api-url sets the curseforge API server packs are looked up in (default https://api.curseforge.com/v1),
+    which needs curseforge-api-key or $CURSEFORGE_API_KEY set to a key from https://console.curseforge.com
+Downloaded files are cached by hash in $XDG_CACHE_HOME/modpacks-rs (~/.cache/modpacks-rs) and reused by later installs,
+    mods are hard linked from the cache and other files are copied, --no-cache downloads everything again without caching
+API responses are cached there too and reused for 10 minutes (metadata-ttl in config.toml, such as 1h), after which the
+    server is asked whether they changed, --refresh asks it right away and reuses the ones that did not change
+--limit-rate caps the combined download speed of every download thread and installer, in bytes per second (such as 5M,
+    500K), it can also be set with limit-rate in config.toml
+--mirror adds a base url files are downloaded from when their own url fails, with the same path (such as
+    https://mirror.example/files/1234/567/mod.jar), it can be repeated or set with the mirrors array in config.toml,
+    curseforge files are also tried on the other curseforge CDN hosts
+Downloads are checked against the size and SHA-1, SHA-256 and SHA-512 hashes their manifest has, files without any hash
+    are downloaded with a warning, or make the install fail with --strict
+Modloader installers are checked against the .sha512 or .sha1 file their maven repository publishes, --strict refuses
+    installers without one and the FTB server installer (server --installer), which has no hash to check
+Requests go through the proxy in $HTTPS_PROXY (or $HTTP_PROXY, $ALL_PROXY) except for hosts listed in $NO_PROXY, config.toml
+    can set proxy, no-proxy, ca-bundle (PEM file of extra trusted certificates), connect-timeout, read-timeout and user-agent
+--java sets the java executable modloader installers and generated start scripts run with, instead of the one on $PATH or in $JAVA_HOME,
+    $MODPACKS_JAVA overrides it in start.sh
+Settings are read from config.toml in the config directory and then modpacks.toml in the working directory, which overrides it,
+    both accept threads, strict, java and the keys above, and flags override both
 Verbs:
 FTB:
 recent: Lists the most recently updated modpacks
@@ -157,137 +382,385 @@ featured: Lists the featured modpacks
 played: Lists the most played modpacks
 installed: Lists the most installed modpacks
 (search term): Searches for modpacks related to a term
-(download id (version|latest)): Downloads a version of a modpack or the latest one
-(server id (version|latest)): Downloads a version of a server or the latest one
+(download id (version|latest) [--instance (prism|multimc)]): Downloads a version of a modpack or the latest one,
+    --instance wraps it in an instance that can be imported into Prism Launcher or MultiMC
+(server id (version|latest) [--installer] [--platform (linux|windows|mac|arm-linux|arm-mac)]): Downloads a version of a server or the latest one and performs a server installation,
+    --installer uses the official FTB server installer instead, --platform selects the installer's platform (defaults to this one), installers for other platforms are only downloaded
+    so --accept-eula, --bundle and --systemd are refused with them
             
 Curseforge:
 (search term): Searches for modpacks related to a term
-(server id (version|latest)): Downloads a version of a modpack or the latest one and performs a server installation
-(download id (version|latest)): Downloads a version of a modpack or the latest one";
-            println!("{}", usage);
-        }
-        _ => {
-            eprintln!("Invalid usage (consider \"help\")");
+(server id (version|latest) [--client-denylist file] [--server-pack|--client-files]): Downloads a version of a modpack or the latest one and performs a server installation,
+    the official server pack is used when the version has one and a curseforge API key is set, --server-pack requires it and --client-files always builds the server from the client files,
+    when building from the client files mods flagged as client only and mods in the denylist (defaults to client-only.txt in the config directory) are skipped
+(download id (version|latest) [--instance (prism|multimc)]): Downloads a version of a modpack or the latest one,
+    --instance wraps it in an instance that can be imported into Prism Launcher or MultiMC
+(install-zip file): Installs a curseforge modpack zip into a directory named after the file, its files are looked up in the
+    curseforge API (which needs curseforge-api-key) and checked against the size and SHA-1 it lists, files it lists no SHA-1 for
+    are handled like other files without a hash, non jar files are placed in resourcepacks with a warning
+
+Modrinth:
+(install (file|url) [--server]): Installs a .mrpack into a directory named after it, --server installs only the
+    server side files and performs a server installation
+
+Export:
+(export dir [--mrpack] [--output file]): Exports a pack installed by modpacks-rs as a curseforge modpack zip, or a modrinth
+    .mrpack with --mrpack, files changed since the installation and files that can't be downloaded are added as overrides
+
+Cache:
+info: Shows the size of the download and API response cache and how much of it installations use
+(prune [--older-than age] [--max-size size]): Removes files not used within age (such as 30d, 12h) and then the least recently
+    used files until the cache fits in size (such as 10G, 500M)
+clear: Removes every cached file
+Files used by installations are never removed, installations are tracked when they are installed and forgotten once deleted
+
+Config:
+show: Prints the effective settings after applying the config files and flags, along with the files they were read from
+
+Server installations (ftb server, cf server, mrpack install --server) generate start.sh and start.bat unless the installer or server pack provides its own, options:
+--accept-eula: Accepts the Minecraft EULA (https://aka.ms/MinecraftEULA) by writing eula.txt
+--port n, --motd text, --max-players n, --online-mode (true|false), --level-type type: Sets the matching key in server.properties, existing keys are kept
+--bundle: Writes a Dockerfile and .dockerignore so the server directory can be built into an image without downloading anything else
+--systemd: Writes a modpack-id.service systemd unit that runs the start script, --systemd-user user sets the user it runs as
+    and --systemd-restart policy its restart policy (defaults to on-failure)";
+
+/// Removes `flag` from `args`, returning whether it was present
+fn takeFlag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(index) => {
+            args.remove(index);
+            true
         }
+        None => false
     }
 }
 
-#[derive(PartialEq)]
-enum PackType {
-    CF,
-    FTB
+/// Removes `option` and the value following it from `args`, returning the value
+fn takeOption(args: &mut Vec<String>, option: &str) -> Option<String> {
+    let index = args.iter().position(|arg| arg == option)?;
+    if index + 1 >= args.len() {
+        panic!("{option} requires a value");
+    }
+    args.remove(index);
+    Some(args.remove(index))
 }
 
-impl PackType {
-    pub fn url(&self) -> &str {
-        match self {
-            Self::CF => "https://api.modpacks.ch/public/curseforge/",
-            Self::FTB => "https://api.modpacks.ch/public/modpack/"
-        }
+fn checkNoFlagsLeft(args: &[String]) {
+    if !args.is_empty() {
+        panic!("Unknown arguments: {}", args.join(" "));
     }
-    pub fn name(&self) -> &str {
+}
+
+/// Parses the flags of the download verbs, which only take the launcher instance format
+fn takeLauncher(mut flags: Vec<String>) -> Option<Launcher> {
+    let launcher = takeOption(&mut flags, "--instance")
+        .map(|name| Launcher::fromName(&name).expect("--instance must be prism or multimc"));
+    checkNoFlagsLeft(&flags);
+    launcher
+}
+
+/// Which side of the game a download is meant for, files exclusive to the other side are skipped
+#[derive(PartialEq, Clone, Copy)]
+enum Side {
+    Client,
+    Server
+}
+
+impl Side {
+    pub fn wants(&self, file: &JsonValue) -> bool {
         match self {
-            Self::CF => "Curseforge",
-            Self::FTB => "FTB"
+            Self::Client => !file["serveronly"].as_bool().unwrap_or(false),
+            Self::Server => !file["clientonly"].as_bool().unwrap_or(false)
         }
     }
 }
 
-// More targets may be added with requests
-#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
-fn getFTBServerURL(id: &String, version: &String) -> String {
-    format!("https://api.modpacks.ch/public/modpack/{}/{}/server/linux", id, version)
+/// The directory user maintained files such as the client only denylist are read from
+fn configDir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os(if cfg!(windows) { "APPDATA" } else { "HOME" }).map(|home| {
+            let mut path = PathBuf::from(home);
+            if !cfg!(windows) {
+                path.push(".config");
+            }
+            path
+        }))?;
+    Some(base.join("modpacks-rs"))
+}
+
+/// Mods known to be client only, each line of the file is either a curseforge project id or the start of a jar name,
+/// lines starting with # are ignored
+#[derive(Default)]
+struct Denylist {
+    projects: Vec<i64>,
+    names: Vec<String>
+}
+
+impl Denylist {
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|it| format!("Failed to read denylist {:?}: {:?}", path, it))?;
+        Ok(Self::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Self {
+        let mut list = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.parse::<i64>() {
+                Ok(project) => list.projects.push(project),
+                Err(_) => list.names.push(line.to_lowercase())
+            }
+        }
+        list
+    }
+
+    /// Loads client-only.txt from the config directory, or an empty list if it doesn't exist
+    pub fn loadDefault() -> Result<Self, String> {
+        match configDir().map(|dir| dir.join("client-only.txt")) {
+            Some(path) if path.exists() => Self::load(&path),
+            _ => Ok(Self::default())
+        }
+    }
+
+    pub fn denies(&self, file: &JsonValue) -> bool {
+        if let Some(project) = file["curseforge"]["project"].as_i64() {
+            if self.projects.contains(&project) {
+                return true;
+            }
+        }
+        let name = file["name"].to_string().to_lowercase();
+        self.names.iter().any(|prefix| name.starts_with(prefix))
+    }
 }
 
-#[cfg(all(target_arch = "x86_64", target_os = "windows"))]
-fn getFTBServerURL(id: &String, version: &String) -> String {
-    format!("https://api.modpacks.ch/public/modpack/{}/{}/server/windows", id, version)
+/// Platform the official FTB server installer is built for
+#[derive(PartialEq, Clone, Copy)]
+enum Platform {
+    Linux,
+    Windows,
+    Mac,
+    ArmLinux,
+    ArmMac
 }
 
-#[cfg(all(target_arch = "aarch64", target_os = "linux"))]
-fn getFTBServerURL(id: &String, version: &String) -> String {
-    format!("https://api.modpacks.ch/public/modpack/{}/{}/server/arm/linux", id, version)
+impl Platform {
+    pub const NAMES: &'static str = "linux|windows|mac|arm-linux|arm-mac";
+
+    pub fn fromName(name: &str) -> Option<Self> {
+        match name {
+            "linux" => Some(Self::Linux),
+            "windows" => Some(Self::Windows),
+            "mac" => Some(Self::Mac),
+            "arm-linux" => Some(Self::ArmLinux),
+            "arm-mac" => Some(Self::ArmMac),
+            _ => None
+        }
+    }
+
+    /// The platform this binary is running on, if an installer exists for it
+    pub fn host() -> Option<Self> {
+        match (env::consts::ARCH, env::consts::OS) {
+            ("x86_64", "linux") => Some(Self::Linux),
+            ("x86_64", "windows") => Some(Self::Windows),
+            ("x86_64", "macos") => Some(Self::Mac),
+            ("aarch64", "linux") => Some(Self::ArmLinux),
+            ("aarch64", "macos") => Some(Self::ArmMac),
+            _ => None
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Linux => "linux",
+            Self::Windows => "windows",
+            Self::Mac => "mac",
+            Self::ArmLinux => "arm/linux",
+            Self::ArmMac => "arm/mac"
+        }
+    }
+
+    pub fn installerName(&self, base: String) -> String {
+        if self == &Self::Windows {
+            base + "installer.exe"
+        } else {
+            base + "installer"
+        }
+    }
 }
 
-#[cfg(all(target_arch = "aarch64", target_os = "macos"))]
-fn getFTBServerURL(id: &String, version: &String) -> String {
-    format!("https://api.modpacks.ch/public/modpack/{}/{}/server/arm/mac", id, version)
+fn getFTBServerURL(ftb: &FTB, id: &str, version: &str, platform: Platform) -> String {
+    format!("{}{}/{}/server/{}", ftb.url(), id, version, platform.path())
 }
 
-#[cfg(all(target_arch = "x86_64", target_os = "macos"))]
-fn getFTBServerURL(id: &String, version: &String) -> String {
-    format!("https://api.modpacks.ch/public/modpack/{}/{}/server/mac", id, version)
+const LOG_TAIL_LINES: usize = 20;
+
+/// Creates the log file installer output is redirected to
+fn createLog(logPath: &str) -> Result<File, String> {
+    File::create(logPath).map_err(|it| format!("Failed to create {logPath}: {:?}", it))
 }
 
-#[cfg(not(target_os = "windows"))]
-fn getInstallerName(base: String) -> String {
-    base + "installer"
+/// Spawns `command` in `dir` with both output streams redirected to `log`
+fn spawnLogged(command: &mut Command, dir: &str, log: &File) -> io::Result<Child> {
+    command.current_dir(dir)
+        .stdout(Stdio::from(log.try_clone()?))
+        .stderr(Stdio::from(log.try_clone()?))
+        .spawn()
 }
 
-#[cfg(target_os = "windows")]
-fn getInstallerName(base: String) -> String {
-    base + "installer.exe" // In case the user wants to run it later
+/// Waits for an installer started with `spawnLogged`, failing with the end of its log if it did not exit successfully
+fn waitLogged(mut proc: Child, logPath: &str, typeName: &str) -> Result<(), String> {
+    let status = proc.wait().map_err(|it| format!("Failed to wait for {typeName} installer: {:?}", it))?;
+    if status.success() {
+        return Ok(());
+    }
+    let log = fs::read_to_string(logPath).unwrap_or_default();
+    let lines: Vec<&str> = log.lines().collect();
+    let tail = lines[lines.len().saturating_sub(LOG_TAIL_LINES)..].join("\n");
+    Err(format!("{typeName} installer failed ({status}), see {logPath}. Last lines of output:\n{tail}"))
 }
 
-fn tryRunJava(javaArgs: &[&str], typeName: &str) -> Result<(), String> {
-    match Command::new("java").args(javaArgs).spawn() {
-        Ok(mut proc) => {
-            proc.wait().map_err(|it| format!("Failed to wait for forge installer: {:?}", it))?;
-        }
-        Err(_) => {
-            // Assuming file not found
+/// Java executable set with --java or in the config files
+static JAVA: OnceLock<String> = OnceLock::new();
+
+fn tryRunJava(javaArgs: &[&str], typeName: &str, dir: &str) -> Result<(), String> {
+    let logPath = format!("{dir}/{typeName}-installer.log");
+    let log = createLog(&logPath)?;
+    if let Some(java) = JAVA.get() {
+        let proc = spawnLogged(Command::new(java).args(javaArgs), dir, &log)
+            .map_err(|it| format!("Failed to spawn {typeName} installer with {java}: {:?}", it))?;
+        println!("Running {typeName} installer, output is logged to {logPath}");
+        return waitLogged(proc, &logPath, typeName);
+    }
+    let proc = match spawnLogged(Command::new("java").args(javaArgs), dir, &log) {
+        Ok(proc) => proc,
+        // Java isn't on the path, try the one in JAVA_HOME
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
             let javaPath = env::var("JAVA_HOME")
-                .map(|path| path + &if cfg!(windows) { "/bin/java.exe" } else { "/bin/java" })
+                .map(|path| path + if cfg!(windows) { "/bin/java.exe" } else { "/bin/java" })
                 .map_err(|it| format!("Failed to find java for running installer: {:?}", it))?;
-            Command::new(javaPath)
-                .args(javaArgs)
-                .spawn()
+            spawnLogged(Command::new(javaPath).args(javaArgs), dir, &log)
                 .map_err(|it| format!("Failed to spawn {typeName} installer: {:?}", it))?
-                .wait()
-                .map_err(|it| format!("Failed to wait for {typeName} installer: {:?}", it))?;
         }
-    }
+        Err(error) => return Err(format!("Failed to spawn {typeName} installer: {:?}", error))
+    };
+    println!("Running {typeName} installer, output is logged to {logPath}");
+    waitLogged(proc, &logPath, typeName)
+}
+
+/// Where the files of a curseforge server installation come from
+#[derive(PartialEq, Clone, Copy)]
+enum ServerFiles {
+    /// The official server pack if the version has one, otherwise the client files
+    Auto,
+    ServerPack,
+    ClientFiles
+}
+
+/// The curseforge file id of the server pack published alongside a version, if it has one
+fn getServerPackFileId(manifest: &JsonValue) -> Option<i64> {
+    manifest["serverPackFileId"].as_i64().filter(|id| *id > 0)
+}
+
+/// Downloads a curseforge server pack and extracts it into the server directory,
+/// server packs are self contained so no modloader installation is performed
+fn downloadServerPack(curseforge: &Curseforge, id: &String, fileId: i64, threads: usize) -> Result<(), String> {
+    println!("Downloading server pack {fileId}");
+    let project = id.parse::<i64>().map_err(|_| format!("{id} is not a curseforge project id"))?;
+    // Downloaded like pack files, so it's verified, cached and fetched from mirrors the same way
+    let mut file = curseforge.getFile(project, fileId)?;
+    file["name"] = "server-pack.zip".into();
+    let basePath = format!("./{id}/");
+    create_dir_all(&basePath)
+        .map_err(|it| format!("Failed to create server directory: {:?}", it))?;
+    downloadFiles(id, &[&file], threads)?;
+
+    let packPath = basePath.clone() + "server-pack.zip";
+    // Server packs are usually wrapped in a single directory named after the pack
+    let archive = File::open(&packPath)
+        .map_err(|it| format!("Failed to open server pack: {:?}", it))
+        .and_then(|file| ZipArchive::new(file).map_err(|it| format!("Failed to parse server pack as zip: {:?}", it)))?;
+    let wrapper = archive.file_names()
+        .next()
+        .and_then(|name| name.split_once('/'))
+        .map(|(root, _)| format!("{root}/"))
+        .filter(|root| archive.file_names().all(|name| name.starts_with(root.as_str())));
+    let file = File::open(&packPath)
+        .map_err(|it| format!("Failed to open server pack: {:?}", it))?;
+    extractZip(file, &basePath, wrapper.as_deref(), false)?;
+    println!("Server pack extracted, use the scripts it provides to start the server");
     Ok(())
 }
 
-fn downloadCFServer(id: String, mut version: String, threads: usize) -> Result<(), String> {
+fn downloadCFServer(curseforge: &Curseforge, id: String, mut version: String, threads: usize, denylist: &Denylist, serverFiles: ServerFiles, options: &ServerOptions) -> Result<(), String> {
     if version == "latest" {
-        version = getLatestVersion(&id, &PackType::CF)?;
-    }
-    downloadPack(&id, version, PackType::CF, threads)?;
-    let mut file = File::open(format!("./{}/manifest.json", id))
-        .map_err(|it| format!("Failed to open manifest: {:?}", it))?;
-    let mut buf = String::new();
-    file.read_to_string(&mut buf).map_err(|it| format!("Failed to read manifest: {:?}", it))?;
-    let manifest = json::parse(&buf).map_err(|it| format!("Manifest is invalid: {:?}", it))?;
-    let mcSection = &manifest["minecraft"];
-    let mcVersion = &mcSection["version"].to_string();
-    let loader = &mcSection["modLoaders"][0]["id"].to_string();
-    let mut split = loader.split('-');
-    let name = split.next().unwrap();
-    let version = split.next().unwrap();
+        version = curseforge.getLatestVersion(&id)?;
+    }
+    if serverFiles != ServerFiles::ClientFiles {
+        let manifest = curseforge.getVersionManifest(&id, &version)?;
+        match getServerPackFileId(&manifest) {
+            Some(_) if serverFiles == ServerFiles::Auto && !curseforge.canGetFiles() => {
+                println!("Skipping the server pack, looking it up needs curseforge-api-key, building the server from the client files");
+            }
+            Some(fileId) => {
+                downloadServerPack(curseforge, &id, fileId, threads)?;
+                return options.apply(&id, &manifest, false);
+            }
+            None if serverFiles == ServerFiles::ServerPack => {
+                return Err("This version has no server pack, omit --server-pack to build the server from the client files".to_string());
+            }
+            None => println!("No server pack available, building the server from the client files")
+        }
+    }
+    let versionManifest = downloadPack(&id, version, curseforge, threads, Some(Side::Server), Some(denylist))?;
+    let targets = curseforge.getTargets(&id, &versionManifest)?;
+    let (name, version) = targets.loader.ok_or("Manifest has no modloader")?;
+    installModloader(&format!("./{id}"), &targets.minecraft, &name, &version)?;
+    options.apply(&id, &versionManifest, true)
+}
+
+/// Downloads a pack for playing, optionally as an instance of `launcher`
+fn downloadClient(id: &String, version: String, source: &dyn PackSource, threads: usize, launcher: Option<Launcher>) -> Result<(), String> {
+    // Client downloads keep every file like they always have, packs don't flag server only files reliably
+    let versionManifest = downloadPack(id, version, source, threads, None, None)?;
+    let launcher = match launcher {
+        Some(launcher) => launcher,
+        None => return Ok(())
+    };
+    let targets = source.getTargets(id, &versionManifest)?;
+    let packManifest = source.getPackManifest(id)?;
+    let name = format!("{} {}", packManifest["name"], versionManifest["name"]);
+    let icon = packManifest["art"].members()
+        .find(|art| art["type"] == "square")
+        .map(|art| art["url"].to_string());
+    instance::makeInstance(id, launcher, &name, &targets, icon)
+}
+
+/// The minecraft version and modloader (name and version) a pack version is made for
+struct Targets {
+    minecraft: String,
+    loader: Option<(String, String)>
+}
 
+/// Downloads and runs the server installer of a modloader inside `dir`
+fn installModloader(dir: &str, mcVersion: &str, name: &str, version: &str) -> Result<(), String> {
     // Maybe quilt support at some point?
     match name {
-        "forge" => {
-            let url = format!("https://maven.minecraftforge.net/net/minecraftforge/forge/{mcVersion}-{version}/forge-{mcVersion}-{version}-installer.jar");
-            let resp = ureq::get(&url)
-                .call()
-                .map_err(|it| format!("Failed to download forge installer: {:?}", it))?;
-            let mut raw: Vec<u8> = vec![];
-            resp.into_reader()
-                .read_to_end(&mut raw)
-                .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
-            env::set_current_dir(format!("./{id}")).map_err(|it| format!("Failed to cd: {:?}", it))?;
-            let path = format!("./installer.jar");
-            let mut installer = File::create(&path)
-                .map_err(|it| format!("Couldn't save installer: {:?}", it))?;
-            installer.write_all(&raw).map_err(|it| format!("Couldn't save installer: {:?}", it))?;
-            let javaArgs = ["-jar", &path, "--installServer"];
-            return tryRunJava(&javaArgs, "forge");
+        "forge" | "neoforge" => {
+            let url = if name == "forge" {
+                format!("https://maven.minecraftforge.net/net/minecraftforge/forge/{mcVersion}-{version}/forge-{mcVersion}-{version}-installer.jar")
+            } else {
+                format!("https://maven.neoforged.net/releases/net/neoforged/neoforge/{version}/neoforge-{version}-installer.jar")
+            };
+            downloadInstaller(&url, name, dir)?;
+            let javaArgs = ["-jar", "installer.jar", "--installServer"];
+            tryRunJava(&javaArgs, name, dir)
         }
         "fabric" => {
-            let mavenMeta = ureq::get("https://maven.fabricmc.net/net/fabricmc/fabric-installer/maven-metadata.xml")
+            let mavenMeta = http::get("https://maven.fabricmc.net/net/fabricmc/fabric-installer/maven-metadata.xml")
                 .call()
                 .map_err(|it| format!("Failed to GET fabric installer maven metadata: {:?}", it))?
                 .into_string()
@@ -295,43 +768,67 @@ fn downloadCFServer(id: String, mut version: String, threads: usize) -> Result<(
             let xml = Document::parse(&mavenMeta)
                 .map_err(|it| format!("Failed to parse metadata as xml: {:?}", it))?;
             let latestVersion = xml.descendants()
-                .filter(|node| node.has_tag_name("release"))
-                .next()
-                .map(|node| node.text());
+                .find(|node| node.has_tag_name("release"))
+                .and_then(|node| node.text());
             match latestVersion {
-                Some(opt) => {
-                    match opt {
-                        Some(loaderVersion) => {
-                            let url = format!("https://maven.fabricmc.net/net/fabricmc/fabric-installer/{loaderVersion}/fabric-installer-{loaderVersion}.jar");
-                            let resp = ureq::get(&url)
-                                .call()
-                                .map_err(|it| format!("Failed to download fabric installer: {:?}", it))?;
-                            let mut raw: Vec<u8> = vec![];
-                            resp.into_reader()
-                                .read_to_end(&mut raw)
-                                .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
-                            let dir = format!("./{id}");
-                            let path = format!("{dir}/installer.jar");
-                            let mut installer = File::create(&path)
-                                .map_err(|it| format!("Couldn't save installer: {:?}", it))?;
-                            installer.write_all(&raw).map_err(|it| format!("Couldn't save installer: {:?}", it))?;
-                            let args = &["-jar", &path, "server", "-dir", &dir, "-mcversion", &mcVersion, "-loader", &version, "-downloadMinecraft"];
-                            return tryRunJava(args, "fabric");
-                        }
-                        None => {
-                            return Err("Invalid maven metadata".to_string());
-                        }
-                    }
+                Some(loaderVersion) => {
+                    let url = format!("https://maven.fabricmc.net/net/fabricmc/fabric-installer/{loaderVersion}/fabric-installer-{loaderVersion}.jar");
+                    downloadInstaller(&url, "fabric", dir)?;
+                    let args = &["-jar", "installer.jar", "server", "-dir", ".", "-mcversion", mcVersion, "-loader", version, "-downloadMinecraft"];
+                    tryRunJava(args, "fabric", dir)
                 }
                 None => {
-                    return Err("Invalid maven metadata".to_string());
+                    Err("Invalid maven metadata".to_string())
                 }
             }
         }
         other => {
-            return Err(format!("Unsupported modloader: {}", other));
+            Err(format!("Unsupported modloader: {}", other))
+        }
+    }
+}
+
+/// Downloads a modloader installer to `dir`/installer.jar, verified against the checksum its maven repository publishes next to it
+fn downloadInstaller(url: &str, name: &str, dir: &str) -> Result<(), String> {
+    let checksum = fetchMavenChecksum(url);
+    if checksum.is_null() && STRICT.load(Ordering::Relaxed) {
+        return Err(format!("Refusing to run the {name} installer without a checksum in --strict mode: {url}"));
+    }
+    let resp = http::get(url)
+        .call()
+        .map_err(|it| format!("Failed to download {name} installer: {:?}", it))?;
+    let mut raw: Vec<u8> = vec![];
+    http::reader(resp)
+        .read_to_end(&mut raw)
+        .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
+    if checksum.is_null() {
+        eprintln!("Warning: {url} has no checksum, the {name} installer can't be verified");
+    } else {
+        verifyFile(&checksum, &raw).map_err(|it| format!("Failed to verify {name} installer: {it}"))?;
+    }
+    let mut installer = File::create(format!("{dir}/installer.jar"))
+        .map_err(|it| format!("Couldn't save installer: {:?}", it))?;
+    installer.write_all(&raw).map_err(|it| format!("Couldn't save installer: {:?}", it))
+}
+
+/// Fetches the .sha512 or .sha1 file of a maven artifact as the hash keys `verifyFile` checks, or null if neither is published
+fn fetchMavenChecksum(url: &str) -> JsonValue {
+    for (key, length) in [("sha512", 128), ("sha1", 40)] {
+        let contents = http::get(&format!("{url}.{key}")).call().ok()
+            .and_then(|resp| resp.into_string().ok());
+        if let Some(hash) = contents.as_deref().and_then(|contents| parseChecksum(contents, length)) {
+            let mut checksum = JsonValue::new_object();
+            checksum[key] = hash.into();
+            return checksum;
         }
     }
+    JsonValue::Null
+}
+
+/// Reads the hash out of a checksum file, which may be followed by the file name
+fn parseChecksum(contents: &str, length: usize) -> Option<&str> {
+    contents.split_whitespace().next()
+        .filter(|hash| hash.len() == length && hash.chars().all(|it| it.is_ascii_hexdigit()))
 }
 
 #[cfg(not(windows))]
@@ -349,100 +846,117 @@ fn makeExecutable(path: &String, file: &File) -> Result<(), String> {
     Ok(())
 }
 
-fn downloadFTBServer(id: String, mut version: String) -> Result<(), String> {
+/// Installs an FTB server by downloading the server side files of the pack and installing its modloader
+fn downloadFTBServer(ftb: &FTB, id: String, mut version: String, threads: usize, options: &ServerOptions) -> Result<(), String> {
     if version == "latest" {
-        version = getLatestVersion(&id, &PackType::FTB)?;
+        version = ftb.getLatestVersion(&id)?;
     }
-    let url = getFTBServerURL(&id, &version);
-    let resp = ureq::get(&url)
+    let manifest = downloadPack(&id, version, ftb, threads, Some(Side::Server), None)?;
+    let targets = ftb.getTargets(&id, &manifest)?;
+    let (name, version) = targets.loader.ok_or("Version manifest has no modloader target")?;
+    installModloader(&format!("./{id}"), &targets.minecraft, &name, &version)?;
+    options.apply(&id, &manifest, true)
+}
+
+/// Installs an FTB server using the official installer binary for `platform`,
+/// an installer for another platform is only downloaded so it can be run on the target machine
+fn runFTBServerInstaller(ftb: &FTB, id: String, mut version: String, platform: Platform, options: &ServerOptions) -> Result<(), String> {
+    let foreign = Platform::host() != Some(platform);
+    let installedServerFlags = options.installedServerFlags();
+    if foreign && !installedServerFlags.is_empty() {
+        return Err(format!("{} can't be used when only saving the installer for {}, run it on the target machine first",
+            installedServerFlags.join(", "), platform.path()));
+    }
+    // Nothing publishes a hash of the native installer
+    if STRICT.load(Ordering::Relaxed) {
+        return Err("Refusing to download the FTB server installer in --strict mode, it has no hash to verify it, leave out --installer to install from the pack's files".to_string());
+    }
+    if version == "latest" {
+        version = ftb.getLatestVersion(&id)?;
+    }
+    let manifest = ftb.getVersionManifest(&id, &version)?;
+    let url = getFTBServerURL(ftb, &id, &version, platform);
+    let resp = http::get(&url)
         .call()
         .map_err(|it| format!("Failed to download installer: {:?}", it))?;
     let mut raw: Vec<u8> = vec![];
-    resp.into_reader().read_to_end(&mut raw).expect("Failed to read all bytes");
+    http::reader(resp)
+        .read_to_end(&mut raw)
+        .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
+    eprintln!("Warning: the FTB server installer has no hash, its contents can't be verified");
     let basePath = "./".to_string() + &id + "/";
     create_dir_all(&basePath)
         .map_err(|it| format!("Failed to create server directory: {:?}", it))?;
-    let installerName = getInstallerName(basePath);
+    let installerName = platform.installerName(basePath.clone());
     let mut file = File::create(&installerName)
         .map_err(|it| format!("Failed to create server file: {:?}", it))?;
-    file.write(&raw).map_err(|it| format!("Failed to write to server file: {:?}", it))?;
+    file.write_all(&raw).map_err(|it| format!("Failed to write to server file: {:?}", it))?;
 
     #[cfg(not(windows))]
-    makeExecutable(&installerName, &file)?;
+    if platform != Platform::Windows {
+        makeExecutable(&installerName, &file)?;
+    }
 
     drop(file); // Otherwise spawning won't work
-    Command::new(installerName)
-        .args([&id, &version, "--auto", "--path", &id])
-        .spawn()
-        .map_err(|it| format!("Failed to spawn installer: {:?}", it))?
-        .wait()
-        .map_err(|it| format!("Failed to wait for installer to complete: {:?}", it))?;
-    Ok(())
-}
-
-fn getLatestVersion(id: &String, packType: &PackType) -> Result<String, String> {
-    let manifest = ureq::get(&(packType.url().to_owned() + &id))
-            .call()
-            .map_err(|it| format!("Failed to get modpack manifest: {:?}", it))?;
-        let decoded = manifest.into_string()
-            .map_err(|it| format!("Failed to parse modpack manifest as string: {:?}", it))?;
-        let parsed = json::parse(&decoded)
-            .map_err(|it| format!("Failed to parse modpack version manifest as json: {:?}", it))?;
-        if let JsonValue::Array(versions) = &parsed["versions"] {
-            let versions = versions.iter();
-            let handled: Vec<&JsonValue>;
-            if packType == &PackType::FTB {
-                handled = versions.rev().collect();
-            } else {
-                handled = versions.collect();
-            }
-            match handled.first() {
-                Some(new) => {
-                    return Ok(new["id"].to_string());
-                }
-                None => {
-                    return Err("Failed to retrieve latest version".to_string());
-                }
-            }
-        } else {
-            return Err("\"versions\" is not an array".to_string());
-        }
+    if foreign {
+        println!("Saved installer for {} to {installerName}, run it on the target machine with: {id} {version} --auto", platform.path());
+        return options.apply(&id, &manifest, false);
+    }
+    let installerPath = fs::canonicalize(&installerName)
+        .map_err(|it| format!("Failed to resolve installer path: {:?}", it))?;
+    let logPath = basePath.clone() + "installer.log";
+    let log = createLog(&logPath)?;
+    let proc = spawnLogged(Command::new(installerPath).args([&id, &version, "--auto", "--path", "."]), &basePath, &log)
+        .map_err(|it| format!("Failed to spawn installer: {:?}", it))?;
+    println!("Running FTB server installer, output is logged to {logPath}");
+    waitLogged(proc, &logPath, "FTB server")?;
+    // The installer generates its own start scripts
+    options.apply(&id, &manifest, false)
 }
 
-fn downloadPack(id: &String, mut version: String, packType: PackType, threads: usize) -> Result<(), String> {
+/// Downloads the files of a pack version, skipping files exclusive to the other side when `side` is set and files in `denylist`,
+/// returning the version manifest
+fn downloadPack(id: &String, mut version: String, source: &dyn PackSource, threads: usize, side: Option<Side>, denylist: Option<&Denylist>) -> Result<JsonValue, String> {
     if version == "latest" {
-        version = getLatestVersion(&id, &packType)?;
+        version = source.getLatestVersion(id)?;
     }
 
-    let url = packType.url().to_owned() + &id + "/" + &version;
-    
-    let manifestResp = ureq::get(&url)
-        .set("User-Agent", USER_AGENT) // API returns empty url otherwise
-        .call()
-        .map_err(|it| format!("Failed to get modpack version manifest: {:?}", it))?;
-    let decoded = manifestResp.into_string()
-        .map_err(|it| format!("Failed to parse modpack version manifest as string: {:?}", it))?;
-    let parsed = json::parse(&decoded)
-        .map_err(|it| format!("Failed to parse modpack version manifest as json: {:?}", it))?;
+    let parsed = source.getVersionManifest(id, &version)?;
+    let files: Vec<&JsonValue> = parsed["files"].members()
+        .filter(|file| {
+            let wanted = side.is_none_or(|side| side.wants(file)) && !denylist.is_some_and(|list| list.denies(file));
+            if !wanted {
+                println!("Skipping {}{}", file["path"], file["name"]);
+            }
+            wanted
+        })
+        .collect();
+    downloadFiles(id, &files, threads)?;
+    source.postProcess(id, &parsed)?;
+
+    let targets = source.getTargets(id, &parsed).ok();
+    state::write(id, source.name(), &parsed, &files, targets.as_ref())?;
+    Ok(parsed)
+}
+
+/// Downloads manifest files into `./{id}`, retrying each failed file a few times
+fn downloadFiles(id: &String, files: &[&JsonValue], threads: usize) -> Result<(), String> {
+    checkUnverified(files)?;
     let mut pool: Option<Arc<Mutex<ThreadPool>>> = None;
-    let files = parsed["files"].members();
     let (send, recv) = mpsc::channel::<Option<(ModpackFile, String)>>();
-    if threads > 1 {
+    if threads > 1 && !files.is_empty() {
         pool = Some(Arc::new(Mutex::new(ThreadPool::new(threads))));
     }
     match pool {
         Some(pool) => {
-            // This feels jank
-            let arr = parsed["files"].clone();
-            let files = arr.members();
             for file in files {
-                let file = file.clone();
+                let file = (*file).clone();
                 let id = id.clone();
                 let send = send.clone();
                 let clone = Arc::clone(&pool);
                 pool.lock().unwrap().execute(move || downloadFileThreaded(ModpackFile::new(file), &id, send, clone));
             }
-            while let Some(Some((failed, error))) = recv.recv().ok() { // mmm
+            while let Ok(Some((failed, error))) = recv.recv() { // mmm
                 let sendClone = send.clone();
                 if failed.failureCount < 6 {
                     let id = id.clone();
@@ -469,72 +983,46 @@ fn downloadPack(id: &String, mut version: String, packType: PackType, threads: u
         }
         None => {
             for file in files {
-                handleFile(ModpackFile::new(file.clone()), id)?;
+                handleFile(ModpackFile::new((*file).clone()), id)?;
             }
         }
     }
-    // Handle overrides
-    if packType == PackType::CF {
-        println!("Extracting overrides");
-        let basePath = "./".to_owned() + &id + "/";
-        let file = File::open(basePath.clone() + "overrides.zip")
-            .map_err(|it| format!("Failed to open overrides file: {:?}", it))?;
-        let mut archive = ZipArchive::new(file)
-            .map_err(|it| format!("Failed to parse overrides as zip: {:?}", it))?;
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i).unwrap();
-            let path = match file.enclosed_name() {
-                Some(path) => {
-                    let mut buf = PathBuf::from(&basePath);
-                    buf.push(path);
-                    let overridePath = "./".to_string() + &id + "/overrides/";
-                    let mut newBuf = PathBuf::new();
-                    if buf.starts_with(overridePath) {
-                        let mut trimmed = false;
-                        for component in buf.components() {
-                            match component {
-                                std::path::Component::CurDir => {
-                                    newBuf.push("./");
-                                }
-                                std::path::Component::Normal(path) => {
-                                    if path != "overrides" || trimmed {
-                                        newBuf.push(path);
-                                    }
-                                    if path == "overrides" {
-                                        trimmed = true;
-                                    }
-                                }
-                                other => {
-                                    return Err(format!("Invalid component: {:?}", other));
-                                }
-                            }
-                        }
-                    } else {
-                        newBuf = buf;
-                    }
-                    newBuf
-                }
-                None => continue
-            };
-            println!("Extracting override: {:?}", path);
-            if file.is_dir() {
-                create_dir_all(path)
-                    .map_err(|it| format!("Failed to create overrides directory: {:?}", it))?;
-            } else {
-                if !path.is_dir() {
-                    if let Some(parent) = path.parent() {
-                        create_dir_all(parent)
-                            .map_err(|it| format!("Failed to create overrides directory: {:?}", it))?;
-                    }
-                    let mut outFile = File::create(path)
-                        .map_err(|it| format!("Failed to create output override file: {:?}", it))?;
-                    io::copy(&mut file, &mut outFile)
-                        .map_err(|it| format!("Failed to copy output override file: {:?}", it))?;
-                }
+    Ok(())
+}
+
+/// Extracts a zip archive into `basePath`, entries inside the `strip` directory are extracted as if they were at the root of the archive
+/// and the other entries are skipped if `onlyStripped` is set
+fn extractZip<R: Read + Seek>(file: R, basePath: &str, strip: Option<&str>, onlyStripped: bool) -> Result<(), String> {
+    let mut archive = ZipArchive::new(file)
+        .map_err(|it| format!("Failed to parse archive as zip: {:?}", it))?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        let path = match file.enclosed_name() {
+            Some(path) => {
+                let path = match strip.map(|strip| path.strip_prefix(strip)) {
+                    Some(Ok(stripped)) => stripped,
+                    Some(Err(_)) if onlyStripped => continue,
+                    _ => path
+                };
+                PathBuf::from(basePath).join(path)
             }
+            None => continue
+        };
+        println!("Extracting: {:?}", path);
+        if file.is_dir() {
+            create_dir_all(path)
+                .map_err(|it| format!("Failed to create directory: {:?}", it))?;
+        } else if !path.is_dir() {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent)
+                    .map_err(|it| format!("Failed to create directory: {:?}", it))?;
+            }
+            let mut outFile = File::create(path)
+                .map_err(|it| format!("Failed to create output file: {:?}", it))?;
+            io::copy(&mut file, &mut outFile)
+                .map_err(|it| format!("Failed to copy output file: {:?}", it))?;
         }
     }
-
     Ok(())
 }
 
@@ -563,9 +1051,9 @@ fn handleFile(mut file: ModpackFile, id: &String) -> Result<(), String> {
     Ok(())
 }
 
-fn downloadFileThreaded(mut file: ModpackFile, id: &String, channel: Sender<Option<(ModpackFile, String)>>, pool: Arc<Mutex<ThreadPool>>) {
+fn downloadFileThreaded(mut file: ModpackFile, id: &str, channel: Sender<Option<(ModpackFile, String)>>, pool: Arc<Mutex<ThreadPool>>) {
     if let Err(error) = maybeDownloadFile(&mut file, id) {
-        channel.send(Some((file, error.clone()))).expect(&format!("Failed to send error message: {}", error));
+        channel.send(Some((file, error.clone()))).unwrap_or_else(|_| panic!("Failed to send error message: {}", error));
     } else {
         let pool = pool.lock().unwrap();
         let count = pool.queued_count() + pool.active_count();
@@ -576,41 +1064,133 @@ fn downloadFileThreaded(mut file: ModpackFile, id: &String, channel: Sender<Opti
     }
 }
 
-fn maybeDownloadFile(file: &mut ModpackFile, id: &String) -> Result<(), String> {
+fn maybeDownloadFile(file: &mut ModpackFile, id: &str) -> Result<(), String> {
     let result = downloadFile(file.file.clone(), id);
     if result.is_err() {
         file.failureCount += 1;
     }
-    return result;
+    result
 }
 
-fn downloadFile(file: JsonValue, id: &String) -> Result<(), String> {
+fn downloadFile(file: JsonValue, id: &str) -> Result<(), String> {
     let hash = file["sha1"].to_string();
-    let url = file["url"].to_string();
     let name = file["name"].to_string();
     let path = "./".to_owned() + id + "/" + &file["path"].to_string();
+    create_dir_all(&path).map_err(|it| format!("Failed to create path: {:?}", it))?;
+    let dest = PathBuf::from(&path).join(&name);
+    if cache::fetch(&hash, &dest).is_some() {
+        println!("Using cached {}{}", path, name);
+        return Ok(());
+    }
     println!("Downloading {}{}", path, name);
-    let resp = ureq::get(&url)
-        .call()
-        .map_err(|it| format!("Failed to get modpack file: {:?}", it))?;
-    let mut raw: Vec<u8> = vec![];
-    resp.into_reader().read_to_end(&mut raw).expect("Failed to read all bytes");
-    let mut hasher = Sha1::new();
-    hasher.update(&raw);
-    let result = hex::encode(hasher.finalize());
+    let raw = fetchFile(&file, |raw| verifyFile(&file, raw))?;
+    cache::store(&hash, &dest, &raw)?;
+    println!("Finished downloading");
+    Ok(())
+}
 
-    if result != hash && !hash.is_empty() { // Expected hashes are empty sometimes
-        return Err(format!("Mismatched hashes, expected: {} found: {}", hash, result));
+/// Checks a download against the size and hashes of its manifest file, each is only checked when the manifest has it
+fn verifyFile(file: &JsonValue, raw: &[u8]) -> Result<(), String> {
+    if let Some(expected) = file["size"].as_u64() {
+        if raw.len() as u64 != expected {
+            return Err(format!("Mismatched sizes, expected: {} bytes found: {} bytes", expected, raw.len()));
+        }
     }
+    checkHash::<Sha1>(file, "sha1", "Mismatched hashes", raw)?;
+    checkHash::<Sha256>(file, "sha256", "Mismatched SHA-256 hashes", raw)?;
+    checkHash::<Sha512>(file, "sha512", "Mismatched SHA-512 hashes", raw)
+}
 
-    create_dir_all(&path).map_err(|it| format!("Failed to create path: {:?}", it))?;
-    let mut file = File::create(path + &name)
-        .map_err(|it| format!("Failed to create file: {:?}", it))?;
-    file.write(&raw).map_err(|it| format!("Failed to write to file: {:?}", it))?;
-    println!("Finished downloading");
+fn checkHash<D: Digest>(file: &JsonValue, key: &str, error: &str, raw: &[u8]) -> Result<(), String> {
+    let expected = file[key].as_str().unwrap_or_default();
+    if expected.is_empty() {
+        return Ok(());
+    }
+    let result = hex::encode(D::digest(raw));
+    if !result.eq_ignore_ascii_case(expected) {
+        return Err(format!("{error}, expected: {} found: {}", expected, result));
+    }
+    Ok(())
+}
+
+/// Whether files without any hash to verify them fail the install instead of being downloaded with a warning
+static STRICT: AtomicBool = AtomicBool::new(false);
+
+/// Warns about files that have no hash to verify them, or fails if --strict is set, before anything is downloaded
+fn checkUnverified(files: &[&JsonValue]) -> Result<(), String> {
+    let unverified: Vec<String> = files.iter()
+        .filter(|file| ["sha1", "sha256", "sha512"].iter().all(|key| file[*key].as_str().unwrap_or_default().is_empty()))
+        .map(|file| format!("{}{}", file["path"], file["name"]))
+        .collect();
+    if unverified.is_empty() {
+        return Ok(());
+    }
+    if STRICT.load(Ordering::Relaxed) {
+        return Err(format!("Refusing to download files without a hash in --strict mode: {}", unverified.join(", ")));
+    }
+    for file in unverified {
+        eprintln!("Warning: {file} has no hash, its contents can't be verified");
+    }
     Ok(())
 }
 
+/// Mirror base urls set with --mirror or in config.toml
+static MIRRORS: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Hosts of the curseforge CDN, which all serve the same paths
+const CURSEFORGE_CDNS: [&str; 3] = ["edge.forgecdn.net", "mediafilez.forgecdn.net", "media.forgecdn.net"];
+
+/// Fetches a manifest file from the first of its download urls that gives a download passing `verify`,
+/// so an outage or corrupt copy on one source doesn't count as a failed attempt
+fn fetchFile(file: &JsonValue, verify: impl Fn(&[u8]) -> Result<(), String>) -> Result<Vec<u8>, String> {
+    let urls = getDownloadUrls(file);
+    let mut error = "No download url".to_string();
+    for (i, url) in urls.iter().enumerate() {
+        let result = http::get(url)
+            .call()
+            .map_err(|it| format!("Failed to get modpack file: {:?}", it))
+            .and_then(|resp| {
+                let mut raw: Vec<u8> = vec![];
+                http::reader(resp).read_to_end(&mut raw).map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
+                Ok(raw)
+            })
+            .and_then(|raw| verify(&raw).map(|_| raw));
+        match result {
+            Ok(raw) => return Ok(raw),
+            Err(it) => {
+                if i + 1 < urls.len() {
+                    println!("Failed to download from {url}, trying the next source");
+                }
+                error = it;
+            }
+        }
+    }
+    Err(error)
+}
+
+/// The url of a manifest file followed by the mirrors listed for it, the same path on each configured mirror
+/// and on the other curseforge CDN hosts, cached files are used before any of these
+fn getDownloadUrls(file: &JsonValue) -> Vec<String> {
+    let url = file["url"].to_string();
+    let mut urls = vec![url.clone()];
+    urls.extend(file["mirrors"].members().map(|mirror| mirror.to_string()));
+    let path = url.split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|start| &rest[start..]))
+        .filter(|_| !url.is_empty());
+    if let Some(path) = path {
+        for mirror in MIRRORS.get().into_iter().flatten() {
+            urls.push(format!("{}{path}", mirror.trim_end_matches('/')));
+        }
+        let host = http::getHost(&url);
+        if CURSEFORGE_CDNS.contains(&host.as_str()) {
+            urls.extend(CURSEFORGE_CDNS.iter().filter(|cdn| **cdn != host).map(|cdn| format!("https://{cdn}{path}")));
+        }
+    }
+    let mut seen: Vec<String> = vec![];
+    urls.retain(|url| !url.is_empty() && !seen.contains(url) && { seen.push(url.clone()); true });
+    urls
+}
+
 fn printPack(id: String, pack: HashMap<&'static str, String>) {
     println!("{}: {}", id, pack["name"]);
     println!("Authors: {}", pack["authors"]);
@@ -618,14 +1198,8 @@ fn printPack(id: String, pack: HashMap<&'static str, String>) {
     println!("Versions:\n{}\n", pack["versions"]);
 }
 
-fn getPackInfo(id: String, packType: PackType) -> Result<HashMap<&'static str, String>, String> {
-    let path = packType.url();
-    let resp = ureq::get(&(path.to_owned() + &id))
-        .call()
-        .map_err(|it| format!("Failed to GET {} modpack info: {:?}", packType.name(), it))?;
-    let string = resp.into_string().map_err(|it| format!("Failed to parse info response as string: {:?}", it))?;
-    let data = json::parse(&string)
-        .map_err(|it| format!("Failed to parse info response as json: {:?}", it))?;
+fn getPackInfo(id: String, source: &dyn PackSource) -> Result<HashMap<&'static str, String>, String> {
+    let data = source.getPackManifest(&id)?;
     let mut info: HashMap<&'static str, String> = HashMap::new();
     info.insert("name", data["name"].to_string());
     let mut authors0: Vec<String> = vec![];
@@ -640,132 +1214,102 @@ fn getPackInfo(id: String, packType: PackType) -> Result<HashMap<&'static str, S
     }
     info.insert("authors", format!("{:?}", authors0));
     info.insert("description", data["description"].to_string());
-    let versions0: &Vec<JsonValue>;
-    if let JsonValue::Array(versions) = &data["versions"] {
-        versions0 = versions;
-    } else {
-        return Err("\"versions\" is not an array".to_string());
-    }
-    let len = versions0.len();
-
-    let mut versionString = String::new();
-    let mut revVersions = versions0.iter().rev();
 
-    if packType == PackType::FTB {
-        for i in 0..len {
-            if i == 3 {
-                break;
-            }
-            let version = revVersions.next().unwrap();
-            versionString += &version["id"].as_i64().unwrap().to_string();
-            versionString += ": ";
-            versionString += version["name"].as_str().unwrap();
-            if i != len - 1 && i != 2 {
-                versionString += "\n";
-            }
-        }
-    } else {
-        for i in 0..len {
-            if i == 3 {
-                break;
-            }
-            let version = &versions0[i];
-            versionString += &version["id"].as_i64().unwrap().to_string();
-            versionString += ": ";
-            versionString += version["name"].as_str().unwrap();
-            if i != len - 1 && i != 2 {
-                versionString += "\n";
-            }
-        }
-    }
+    let versionString = source.listVersions(&data)?.iter()
+        .take(3)
+        .map(|version| format!("{}: {}", version["id"], version["name"]))
+        .collect::<Vec<String>>()
+        .join("\n");
     info.insert("versions", versionString);
 
     Ok(info)
 }
 
-fn getPopular() -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = ureq::get("https://api.modpacks.ch/public/modpack/updated/10")
-        .call()
-        .map_err(|it| format!("Failed to GET recent modpacks: {:?}", it))?;
+fn getPopular(ftb: &FTB) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
     let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-    for pack in parsePacks(resp)? {
-        info.insert(pack.clone(), getPackInfo(pack, PackType::FTB)?);
+    for pack in ftb.listPacks("updated", "recent modpacks")? {
+        info.insert(pack.clone(), getPackInfo(pack, ftb)?);
     }
     Ok(info)
 }
 
-fn getFeatured() -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = ureq::get("https://api.modpacks.ch/public/modpack/featured/10")
-        .call()
-        .map_err(|it| format!("Failed to GET featured modpacks: {:?}", it))?;
+fn getFeatured(ftb: &FTB) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
     let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-    for pack in parsePacks(resp)? {
-        info.insert(pack.clone(), getPackInfo(pack, PackType::FTB)?);
+    for pack in ftb.listPacks("featured", "featured modpacks")? {
+        info.insert(pack.clone(), getPackInfo(pack, ftb)?);
     }
     Ok(info)
 }
 
-fn getPlayed() -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = ureq::get("https://api.modpacks.ch/public/modpack/popular/plays/10")
-        .call()
-        .map_err(|it| format!("Failed to GET most played modpacks: {:?}", it))?;
+fn getPlayed(ftb: &FTB) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
     let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-    for pack in parsePacks(resp)? {
-        info.insert(pack.clone(), getPackInfo(pack, PackType::FTB)?);
+    for pack in ftb.listPacks("popular/plays", "most played modpacks")? {
+        info.insert(pack.clone(), getPackInfo(pack, ftb)?);
     }
     Ok(info)
 }
 
-fn getInstalled() -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = ureq::get("https://api.modpacks.ch/public/modpack/popular/installs/10")
-        .call()
-        .map_err(|it| format!("Failed to GET most installed modpacks: {:?}", it))?;
+fn getInstalled(ftb: &FTB) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
     let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-    for pack in parsePacks(resp)? {
-        info.insert(pack.clone(), getPackInfo(pack, PackType::FTB)?);
+    for pack in ftb.listPacks("popular/installs", "most installed modpacks")? {
+        info.insert(pack.clone(), getPackInfo(pack, ftb)?);
     }
     Ok(info)
 }
 
-fn search(term: String) -> Result<Response, String> {
-    ureq::get(&format!("https://api.modpacks.ch/public/modpack/search/5?term={}", &term))
-        .call()
-        .map_err(|it| format!("Failed to GET modpack search: {:?}", it))
-}
-
-fn searchFTB(term: String) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = search(term)?;
+fn searchPacks(source: &dyn PackSource, term: &str) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
     let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-    for pack in parsePacks(resp)? {
-        info.insert(pack.clone(), getPackInfo(pack, PackType::FTB)?);
+    for pack in source.search(term)? {
+        info.insert(pack.clone(), getPackInfo(pack, source)?);
     }
     Ok(info)
 }
 
-fn searchCF(term: String) -> Result<HashMap<String, HashMap<&'static str, String>>, String> {
-    let resp = search(term)?;
-    let string = resp.into_string().map_err(|it| format!("Failed to parse response as string: {:?}", it))?;
-    let data = json::parse(&string)
-        .map_err(|it| format!("Failed to parse response as json: {:?}", it))?;
-    if let JsonValue::Array(arr) = &data["curseforge"] {
-        let packs: Vec<String> = arr.iter().map(|it| it.to_string()).collect();
-        let mut info: HashMap<String, HashMap<&'static str, String>> = HashMap::new();
-        for pack in packs {
-            info.insert(pack.clone(), getPackInfo(pack, PackType::CF)?);
+#[cfg(test)]
+mod tests {
+    use json::object;
+
+    use super::*;
+
+    fn file(name: &str, project: Option<i64>) -> JsonValue {
+        let mut file = object! { name: name, path: "./mods/" };
+        if let Some(project) = project {
+            file["curseforge"] = object! { project: project, file: 1 };
         }
-        Ok(info)
-    } else {
-        Err("Invalid format".to_string())
+        file
     }
-}
 
-fn parsePacks(resp: Response) -> Result<Vec<String>, String> {
-    let string = resp.into_string().map_err(|it| format!("Failed to parse response as string: {:?}", it))?;
-    let data = json::parse(&string)
-        .map_err(|it| format!("Failed to parse response as json: {:?}", it))?;
-    if let JsonValue::Array(arr) = &data["packs"] {
-        Ok(arr.iter().map(|it| it.to_string()).collect())
-    } else {
-        Err("Invalid format".to_string())
+    #[test]
+    fn denylistMatchesProjectsAndNamePrefixes() {
+        let list = Denylist::parse("# Client only mods\n238222\n  OptiFine \n\n#journeymap\nsodium-\n");
+        assert!(list.denies(&file("anything.jar", Some(238222))));
+        assert!(list.denies(&file("optifine_1.20.1_HD_U_I6.jar", None)));
+        assert!(list.denies(&file("Sodium-fabric-0.5.3.jar", Some(394468))));
+        assert!(!list.denies(&file("journeymap-1.20.1.jar", None)));
+        assert!(!list.denies(&file("jei-1.20.1.jar", Some(238223))));
+        assert!(!list.denies(&file("my-sodium-addon.jar", None)));
+    }
+
+    #[test]
+    fn serversSkipClientOnlyFiles() {
+        let mut clientOnly = file("client.jar", None);
+        clientOnly["clientonly"] = true.into();
+        let mut serverOnly = file("server.jar", None);
+        serverOnly["serveronly"] = true.into();
+        let shared = file("shared.jar", None);
+        assert!(!Side::Server.wants(&clientOnly));
+        assert!(Side::Server.wants(&serverOnly));
+        assert!(Side::Server.wants(&shared));
+        assert!(Side::Client.wants(&clientOnly));
+        assert!(!Side::Client.wants(&serverOnly));
+    }
+
+    #[test]
+    fn mavenChecksumsAreParsed() {
+        let sha1 = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+        assert_eq!(parseChecksum(sha1, 40), Some(sha1));
+        assert_eq!(parseChecksum(&format!("{sha1}  forge-installer.jar\n"), 40), Some(sha1));
+        assert_eq!(parseChecksum(sha1, 128), None);
+        assert_eq!(parseChecksum("<html>Not Found</html>", 40), None);
     }
 }
@@ -0,0 +1,124 @@
+use std::{
+    fs::{self, create_dir_all},
+    io::{Cursor, Read},
+    path::{Component, Path}
+};
+
+use json::{array, object, JsonValue};
+use zip::ZipArchive;
+
+use crate::{downloadFiles, extractZip, http, installModloader, server::ServerOptions, state, Side, Targets};
+
+/// Installs a modrinth pack from a file or url into a directory named after it,
+/// server installations also install the modloader and apply `server`
+pub fn install(source: &str, threads: usize, server: Option<&ServerOptions>) -> Result<(), String> {
+    let (raw, fileName) = if source.starts_with("http://") || source.starts_with("https://") {
+        let resp = http::get(source)
+            .call()
+            .map_err(|it| format!("Failed to download modrinth pack: {:?}", it))?;
+        let mut raw: Vec<u8> = vec![];
+        http::reader(resp)
+            .read_to_end(&mut raw)
+            .map_err(|it| format!("Failed to read all bytes: {:?}", it))?;
+        let fileName = source.split(['?', '#']).next().unwrap_or_default().rsplit('/').next().unwrap_or_default().to_string();
+        (raw, fileName)
+    } else {
+        let raw = fs::read(source).map_err(|it| format!("Failed to read {source}: {:?}", it))?;
+        let fileName = Path::new(source).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        (raw, fileName)
+    };
+    let id = fileName.trim_end_matches(".mrpack").to_string();
+    if id.is_empty() {
+        return Err(format!("Couldn't name the pack directory after {source}"));
+    }
+
+    let mut archive = ZipArchive::new(Cursor::new(&raw))
+        .map_err(|it| format!("Failed to parse modrinth pack as zip: {:?}", it))?;
+    let mut buf = String::new();
+    archive.by_name("modrinth.index.json")
+        .map_err(|it| format!("The pack has no modrinth.index.json: {:?}", it))?
+        .read_to_string(&mut buf)
+        .map_err(|it| format!("Failed to read index: {:?}", it))?;
+    let index = json::parse(&buf).map_err(|it| format!("Index is invalid: {:?}", it))?;
+    if index["game"] != "minecraft" {
+        return Err(format!("Unsupported game: {}", index["game"]));
+    }
+    create_dir_all(format!("./{id}"))
+        .map_err(|it| format!("Failed to create pack directory: {:?}", it))?;
+
+    let side = if server.is_some() { Side::Server } else { Side::Client };
+    let mut files: Vec<JsonValue> = vec![];
+    for entry in index["files"].members() {
+        let file = toManifestFile(entry)?;
+        if side.wants(&file) {
+            files.push(file);
+        } else {
+            println!("Skipping {}{}", file["path"], file["name"]);
+        }
+    }
+    let files: Vec<&JsonValue> = files.iter().collect();
+    downloadFiles(&id, &files, threads)?;
+
+    println!("Extracting overrides");
+    let basePath = format!("./{id}/");
+    let sideOverrides = if server.is_some() { "server-overrides" } else { "client-overrides" };
+    extractZip(Cursor::new(&raw), &basePath, Some("overrides"), true)?;
+    extractZip(Cursor::new(&raw), &basePath, Some(sideOverrides), true)?;
+
+    let targets = getTargets(&index["dependencies"])?;
+    let versionManifest = object! { name: index["versionId"].clone() };
+    state::write(&id, "Modrinth", &versionManifest, &files, Some(&targets))?;
+    println!("Installed {} into ./{id}", index["name"]);
+
+    if let Some(options) = server {
+        let (name, version) = targets.loader.as_ref().ok_or("The pack has no modloader")?;
+        installModloader(&format!("./{id}"), &targets.minecraft, name, version)?;
+        // Modrinth packs have no recommended specs, only the minecraft version is used
+        let manifest = object! { targets: array![object! { type: "game", name: "minecraft", version: targets.minecraft.clone() }] };
+        options.apply(&id, &manifest, true)?;
+    }
+    Ok(())
+}
+
+/// Converts an index file to the manifest file format the downloader uses
+fn toManifestFile(entry: &JsonValue) -> Result<JsonValue, String> {
+    let path = entry["path"].to_string();
+    // The spec requires rejecting paths that escape the pack directory
+    if !Path::new(&path).components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(format!("Refusing to install file outside of the pack: {path}"));
+    }
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("./{dir}/"), name.to_string()),
+        None => ("./".to_string(), path.clone())
+    };
+    let mut downloads = entry["downloads"].members().map(|url| url.to_string());
+    let url = downloads.next().ok_or(format!("{path} has no download url"))?;
+    Ok(object! {
+        path: dir,
+        name: name,
+        url: url,
+        mirrors: downloads.collect::<Vec<String>>(),
+        sha1: entry["hashes"]["sha1"].clone(),
+        sha512: entry["hashes"]["sha512"].clone(),
+        size: entry["fileSize"].clone(),
+        clientonly: entry["env"]["server"] == "unsupported",
+        serveronly: entry["env"]["client"] == "unsupported"
+    })
+}
+
+/// Reads the minecraft version and modloader from the dependencies of the index
+fn getTargets(dependencies: &JsonValue) -> Result<Targets, String> {
+    let minecraft = dependencies["minecraft"].as_str().ok_or("The pack doesn't depend on a minecraft version")?;
+    let loader = dependencies.entries()
+        .find_map(|(key, version)| {
+            let name = match key {
+                "forge" => "forge",
+                "neoforge" => "neoforge",
+                "fabric-loader" => "fabric",
+                "quilt-loader" => "quilt",
+                _ => return None
+            };
+            Some((name.to_string(), version.to_string()))
+        });
+    Ok(Targets { minecraft: minecraft.to_string(), loader })
+}
@@ -0,0 +1,401 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path
+};
+
+use json::JsonValue;
+
+/// Memory given to servers whose version manifest has no recommended specs, in megabytes
+const DEFAULT_MEMORY: i64 = 4096;
+const DEFAULT_PORT: &str = "25565";
+const DEFAULT_RESTART: &str = "on-failure";
+/// Scripts that start a server in order of preference, the first one is generated by us and the rest by installers
+const START_SCRIPTS: [&str; 4] = ["start.sh", "run.sh", "start-server.sh", "startserver.sh"];
+
+/// Options applied to a server directory once the installation has finished
+pub struct ServerOptions {
+    pub acceptEula: bool,
+    /// server.properties keys and values to set
+    pub properties: Vec<(&'static str, String)>,
+    /// Whether to write a Dockerfile for the server
+    pub bundle: bool,
+    pub systemd: Option<SystemdOptions>
+}
+
+/// Options of the generated systemd unit
+pub struct SystemdOptions {
+    pub user: Option<String>,
+    pub restart: String
+}
+
+impl ServerOptions {
+    /// The flags that need a server installed in place, as opposed to an installer saved for another machine
+    pub fn installedServerFlags(&self) -> Vec<&'static str> {
+        let mut flags = vec![];
+        if self.acceptEula {
+            flags.push("--accept-eula");
+        }
+        if self.bundle {
+            flags.push("--bundle");
+        }
+        if self.systemd.is_some() {
+            flags.push("--systemd");
+        }
+        flags
+    }
+
+    /// Takes the server options out of the command line flags
+    pub fn fromFlags(flags: &mut Vec<String>) -> Self {
+        let mut properties: Vec<(&'static str, String)> = vec![];
+        if let Some(port) = crate::takeOption(flags, "--port") {
+            port.parse::<u16>().expect("--port must be a port number");
+            properties.push(("server-port", port));
+        }
+        if let Some(motd) = crate::takeOption(flags, "--motd") {
+            properties.push(("motd", motd));
+        }
+        if let Some(maxPlayers) = crate::takeOption(flags, "--max-players") {
+            maxPlayers.parse::<u32>().expect("--max-players must be a number");
+            properties.push(("max-players", maxPlayers));
+        }
+        if let Some(onlineMode) = crate::takeOption(flags, "--online-mode") {
+            onlineMode.parse::<bool>().expect("--online-mode must be true or false");
+            properties.push(("online-mode", onlineMode));
+        }
+        if let Some(levelType) = crate::takeOption(flags, "--level-type") {
+            properties.push(("level-type", levelType));
+        }
+        Self {
+            acceptEula: crate::takeFlag(flags, "--accept-eula"),
+            properties,
+            bundle: crate::takeFlag(flags, "--bundle"),
+            systemd: if crate::takeFlag(flags, "--systemd") {
+                Some(SystemdOptions {
+                    user: crate::takeOption(flags, "--systemd-user"),
+                    restart: crate::takeOption(flags, "--systemd-restart").unwrap_or_else(|| DEFAULT_RESTART.to_string())
+                })
+            } else {
+                None
+            }
+        }
+    }
+
+    /// Applies the options to the server directory of pack `id` using the version manifest of the installed pack,
+    /// start scripts are only generated if `generateScripts` is set as some installations come with their own
+    pub fn apply(&self, id: &str, manifest: &JsonValue, generateScripts: bool) -> Result<(), String> {
+        let dir = &format!("./{id}");
+        if generateScripts {
+            writeStartScripts(dir, manifest, crate::JAVA.get().map(String::as_str))?;
+        }
+        if self.acceptEula {
+            writeEula(dir)?;
+        }
+        if !self.properties.is_empty() {
+            writeProperties(dir, &self.properties)?;
+        }
+        if self.bundle {
+            let port = self.properties.iter()
+                .find(|(name, _)| *name == "server-port")
+                .map_or(DEFAULT_PORT, |(_, port)| port.as_str());
+            writeDockerfile(dir, manifest, port)?;
+        }
+        if let Some(systemd) = &self.systemd {
+            writeSystemdUnit(id, dir, systemd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the script that starts the server in `dir`
+fn findStartScript(dir: &str) -> Option<&'static str> {
+    START_SCRIPTS.into_iter().find(|script| Path::new(dir).join(script).exists())
+}
+
+/// The java major version the pack needs, from its runtime target or otherwise from its minecraft version
+fn getJavaVersion(manifest: &JsonValue) -> u32 {
+    let target = |kind: &str| manifest["targets"].members()
+        .find(|target| target["type"] == kind)
+        .map(|target| target["version"].to_string());
+    if let Some(java) = target("runtime") {
+        if let Some(major) = java.split('.').next().and_then(|major| major.parse::<u32>().ok()) {
+            return major;
+        }
+    }
+    let game = target("game").unwrap_or_default();
+    let mut parts = game.split('.').skip(1).map(|part| part.parse::<u32>().ok());
+    let minor = parts.next().flatten();
+    let patch = parts.next().flatten().unwrap_or(0);
+    match minor {
+        Some(minor) if minor <= 16 => 8,
+        Some(17..=19) => 17,
+        // 1.20.5 moved to java 21
+        Some(20) if patch < 5 => 17,
+        _ => 21
+    }
+}
+
+/// Writes a Dockerfile that copies the installed server into an image and runs its start script
+fn writeDockerfile(dir: &str, manifest: &JsonValue, port: &str) -> Result<(), String> {
+    let script = findStartScript(dir).ok_or("Couldn't find a start script to use as the image entrypoint")?;
+    let java = getJavaVersion(manifest);
+    let dockerfile = format!("# Everything the server needs is copied from this directory, only the base image is pulled\n\
+FROM eclipse-temurin:{java}-jre\n\
+ENV MODPACKS_JAVA=java\n\
+WORKDIR /server\n\
+COPY . /server\n\
+RUN chmod +x {script}\n\
+EXPOSE {port}\n\
+VOLUME /server/world\n\
+ENTRYPOINT [\"./{script}\"]\n");
+    writeFile(&format!("{dir}/Dockerfile"), &dockerfile)?;
+    let ignore = "Dockerfile\n.dockerignore\nmodpack-*.service\ninstaller\ninstaller.exe\ninstaller.jar\n*installer.log\nserver-pack.zip\noverrides.zip\nworld/\nlogs/\ncrash-reports/\n";
+    writeFile(&format!("{dir}/.dockerignore"), ignore)?;
+    if !Path::new(dir).join("eula.txt").exists() {
+        println!("eula.txt is missing, the server won't start in the container unless --accept-eula is passed");
+    }
+    println!("Generated Dockerfile and .dockerignore, build with: docker build -t <name> {dir}");
+    Ok(())
+}
+
+/// Writes a systemd unit running the start script of the server, the unit refers to the directory by its absolute path
+fn writeSystemdUnit(id: &str, dir: &str, options: &SystemdOptions) -> Result<(), String> {
+    let script = findStartScript(dir).ok_or("Couldn't find a start script for the systemd unit to run")?;
+    let absolute = fs::canonicalize(dir)
+        .map_err(|it| format!("Failed to resolve server directory: {:?}", it))?;
+    let absolute = absolute.to_string_lossy();
+    let user = match &options.user {
+        Some(user) => format!("User={user}\n"),
+        None => String::new()
+    };
+    let unit = format!("[Unit]\n\
+Description=Modpack server {id}\n\
+Wants=network-online.target\n\
+After=network-online.target\n\
+\n\
+[Service]\n\
+Type=simple\n\
+{user}\
+WorkingDirectory={}\n\
+ExecStart=/bin/sh {}\n\
+Restart={}\n\
+RestartSec=10\n\
+# The server saves the world and exits with 143 on SIGTERM\n\
+SuccessExitStatus=0 143\n\
+\n\
+[Install]\n\
+WantedBy=multi-user.target\n", escapeSpecifiers(&absolute), quoteExecArgument(&format!("{absolute}/{script}")), options.restart);
+    let name = format!("modpack-{id}.service");
+    writeFile(&format!("{dir}/{name}"), &unit)?;
+    println!("Generated {name}, enable it with: systemctl enable --now \"{absolute}/{name}\"");
+    Ok(())
+}
+
+/// Escapes the % specifiers systemd expands in unit settings
+fn escapeSpecifiers(value: &str) -> String {
+    value.replace('%', "%%")
+}
+
+/// Quotes an argument of ExecStart so paths with spaces stay one argument and nothing in them is expanded
+fn quoteExecArgument(value: &str) -> String {
+    let escaped = escapeSpecifiers(value).replace('\\', "\\\\").replace('"', "\\\"").replace('$', "$$");
+    format!("\"{escaped}\"")
+}
+
+/// How the installed server is launched, `unix` and `windows` are the arguments passed to java after the memory flags
+struct Launch {
+    unix: String,
+    windows: String
+}
+
+/// Finds what the modloader installer produced in `dir`
+fn findLaunch(dir: &str) -> Option<Launch> {
+    // Forge 1.17+ and neoforge put their arguments in a file inside libraries
+    for loaderPath in ["libraries/net/minecraftforge/forge", "libraries/net/neoforged/neoforge", "libraries/net/neoforged/forge"] {
+        let versions = match fs::read_dir(Path::new(dir).join(loaderPath)) {
+            Ok(versions) => versions,
+            Err(_) => continue
+        };
+        for version in versions.flatten() {
+            let version = version.file_name().to_string_lossy().to_string();
+            let argsPath = format!("{loaderPath}/{version}");
+            if Path::new(dir).join(&argsPath).join("unix_args.txt").exists() {
+                return Some(Launch {
+                    unix: format!("@{argsPath}/unix_args.txt"),
+                    windows: format!("@{argsPath}/win_args.txt")
+                });
+            }
+        }
+    }
+    let jar = if Path::new(dir).join("fabric-server-launch.jar").exists() {
+        Some("fabric-server-launch.jar".to_string())
+    } else {
+        fs::read_dir(dir).ok()?
+            .flatten()
+            .map(|entry| entry.file_name().to_string_lossy().to_string())
+            .find(|name| name.starts_with("forge-") && name.ends_with(".jar") && !name.contains("installer"))
+    }?;
+    Some(Launch {
+        unix: format!("-jar {jar}"),
+        windows: format!("-jar {jar}")
+    })
+}
+
+/// Writes start.sh and start.bat using the memory recommended by the version manifest, running `java` instead of the java on the path if set
+fn writeStartScripts(dir: &str, manifest: &JsonValue, java: Option<&str>) -> Result<(), String> {
+    let launch = findLaunch(dir).ok_or("Couldn't find the server jar or arguments produced by the modloader installer")?;
+    let recommended = manifest["specs"]["recommended"].as_i64().filter(|it| *it > 0).unwrap_or(DEFAULT_MEMORY);
+    let minimum = manifest["specs"]["minimum"].as_i64().filter(|it| *it > 0 && *it <= recommended).unwrap_or(recommended);
+    let memory = format!("-Xms{minimum}M -Xmx{recommended}M");
+
+    // $MODPACKS_JAVA overrides a configured path, such as in the image --bundle builds where it doesn't exist
+    let (unixJava, windowsJava) = match java {
+        Some(java) => (format!("\"${{MODPACKS_JAVA:-{java}}}\""), format!("\"{java}\"")),
+        None => ("java".to_string(), "java".to_string())
+    };
+    let unixPath = format!("{dir}/start.sh");
+    let unix = format!("#!/bin/sh\ncd \"$(dirname \"$0\")\"\nexec {unixJava} {memory} {} nogui \"$@\"\n", launch.unix);
+    let file = writeFile(&unixPath, &unix)?;
+    #[cfg(not(windows))]
+    crate::makeExecutable(&unixPath, &file)?;
+    drop(file);
+
+    let windows = format!("@echo off\r\ncd /d \"%~dp0\"\r\n{windowsJava} {memory} {} nogui %*\r\npause\r\n", launch.windows);
+    writeFile(&format!("{dir}/start.bat"), &windows)?;
+    println!("Generated start.sh and start.bat");
+    Ok(())
+}
+
+fn writeEula(dir: &str) -> Result<(), String> {
+    writeFile(&format!("{dir}/eula.txt"), "#By changing the setting below to TRUE you are indicating your agreement to our EULA (https://aka.ms/MinecraftEULA).\neula=true\n")?;
+    println!("Accepted the Minecraft EULA");
+    Ok(())
+}
+
+/// Sets `properties` in server.properties, keeping the other keys of an existing file untouched
+fn writeProperties(dir: &str, properties: &[(&'static str, String)]) -> Result<(), String> {
+    let path = format!("{dir}/server.properties");
+    let existing = fs::read_to_string(&path).unwrap_or_default();
+    writeFile(&path, &updateProperties(&existing, properties))?;
+    println!("Updated server.properties");
+    Ok(())
+}
+
+/// Sets `properties` in the contents of a server.properties file, keeping every other line as it is
+fn updateProperties(existing: &str, properties: &[(&'static str, String)]) -> String {
+    let mut remaining: Vec<&(&str, String)> = properties.iter().collect();
+    let mut lines: Vec<String> = vec![];
+    for line in existing.lines() {
+        let key = line.split(['=', ':']).next().unwrap_or_default().trim();
+        match remaining.iter().position(|(name, _)| *name == key) {
+            Some(index) if !line.trim_start().starts_with('#') => {
+                let (name, value) = remaining.remove(index);
+                lines.push(format!("{name}={}", escapeProperty(value)));
+            }
+            _ => lines.push(line.to_string())
+        }
+    }
+    for (name, value) in remaining {
+        lines.push(format!("{name}={}", escapeProperty(value)));
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Escapes a value the way java's Properties expects, server.properties is read as ISO 8859-1
+fn escapeProperty(value: &str) -> String {
+    let mut escaped = String::new();
+    for char in value.chars() {
+        match char {
+            '\\' => escaped += "\\\\",
+            ' '..='~' => escaped.push(char),
+            other => {
+                let mut units = [0u16; 2];
+                for unit in other.encode_utf16(&mut units) {
+                    escaped += &format!("\\u{:04x}", unit);
+                }
+            }
+        }
+    }
+    escaped
+}
+
+fn writeFile(path: &str, contents: &str) -> Result<File, String> {
+    let mut file = File::create(path)
+        .map_err(|it| format!("Failed to create {path}: {:?}", it))?;
+    file.write_all(contents.as_bytes())
+        .map_err(|it| format!("Failed to write {path}: {:?}", it))?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startScriptsUseTheConfiguredJava() {
+        let dir = std::env::temp_dir().join(format!("modpacks-rs-scripts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("fabric-server-launch.jar"), b"").unwrap();
+        let dir = dir.to_str().unwrap();
+        let manifest = json::object! { specs: json::object! { minimum: 4096, recommended: 6144 } };
+
+        writeStartScripts(dir, &manifest, None).unwrap();
+        let unix = fs::read_to_string(format!("{dir}/start.sh")).unwrap();
+        assert!(unix.contains("exec java -Xms4096M -Xmx6144M -jar fabric-server-launch.jar nogui"));
+
+        writeStartScripts(dir, &manifest, Some("/opt/jdk 21/bin/java")).unwrap();
+        let unix = fs::read_to_string(format!("{dir}/start.sh")).unwrap();
+        let windows = fs::read_to_string(format!("{dir}/start.bat")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(unix.contains("exec \"${MODPACKS_JAVA:-/opt/jdk 21/bin/java}\" -Xms4096M"));
+        assert!(windows.contains("\"/opt/jdk 21/bin/java\" -Xms4096M"));
+    }
+
+    #[test]
+    fn javaVersionFollowsTheMinecraftVersion() {
+        let game = |version: &str| json::object! { targets: json::array![json::object! { type: "game", version: version }] };
+        assert_eq!(getJavaVersion(&game("1.16.5")), 8);
+        assert_eq!(getJavaVersion(&game("1.18.2")), 17);
+        assert_eq!(getJavaVersion(&game("1.20")), 17);
+        assert_eq!(getJavaVersion(&game("1.20.1")), 17);
+        assert_eq!(getJavaVersion(&game("1.20.4")), 17);
+        assert_eq!(getJavaVersion(&game("1.20.5")), 21);
+        assert_eq!(getJavaVersion(&game("1.21.1")), 21);
+        let runtime = json::object! { targets: json::array![json::object! { type: "runtime", version: "17.0.8" }, json::object! { type: "game", version: "1.21" }] };
+        assert_eq!(getJavaVersion(&runtime), 17);
+    }
+
+    #[test]
+    fn systemdUnitsQuoteTheScriptPath() {
+        let dir = std::env::temp_dir().join(format!("modpacks-rs unit 100% {}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("start.sh"), "").unwrap();
+        let absolute = fs::canonicalize(&dir).unwrap().to_string_lossy().replace('%', "%%");
+        let dir = dir.to_str().unwrap();
+        writeSystemdUnit("1", dir, &SystemdOptions { user: None, restart: "on-failure".to_string() }).unwrap();
+        let unit = fs::read_to_string(format!("{dir}/modpack-1.service")).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        assert!(unit.contains(&format!("WorkingDirectory={absolute}\n")));
+        assert!(unit.contains(&format!("ExecStart=/bin/sh \"{absolute}/start.sh\"\n")));
+    }
+
+    #[test]
+    fn existingKeysAreReplacedInPlace() {
+        let existing = "#Minecraft server properties\nmax-players=20\nserver-port=25565\n";
+        let updated = updateProperties(existing, &[("server-port", "25570".to_string())]);
+        assert_eq!(updated, "#Minecraft server properties\nmax-players=20\nserver-port=25570\n");
+    }
+
+    #[test]
+    fn commentedKeysAreKeptAndNewKeysAppended() {
+        let existing = "#motd=Old\nlevel-type=default\n";
+        let updated = updateProperties(existing, &[("motd", "New".to_string()), ("online-mode", "false".to_string())]);
+        assert_eq!(updated, "#motd=Old\nlevel-type=default\nmotd=New\nonline-mode=false\n");
+    }
+
+    #[test]
+    fn nonAsciiValuesAreEscaped() {
+        assert_eq!(escapeProperty("Café \\ 🎮"), "Caf\\u00e9 \\\\ \\ud83c\\udfae");
+        assert_eq!(updateProperties("", &[("motd", "Ünïcode".to_string())]), "motd=\\u00dcn\\u00efcode\n");
+    }
+}
@@ -0,0 +1,238 @@
+use std::{
+    fs::File,
+    io::Read
+};
+
+use json::{object, JsonValue};
+
+use crate::{cache, config::DEFAULT_CURSEFORGE_API_URL, extractZip, Targets, USER_AGENT};
+
+/// A place packs are searched for and downloaded from
+pub trait PackSource {
+    /// Shown in messages and recorded in the install state
+    fn name(&self) -> &str;
+
+    /// Searches for packs matching `term`, returning their ids
+    fn search(&self, term: &str) -> Result<Vec<String>, String>;
+
+    /// Gets the manifest of a pack, which holds its details and versions
+    fn getPackManifest(&self, id: &str) -> Result<JsonValue, String>;
+
+    /// Lists the versions of a pack from its manifest, newest first
+    fn listVersions(&self, packManifest: &JsonValue) -> Result<Vec<JsonValue>, String>;
+
+    /// Gets the manifest of a pack version, which lists its files and targets
+    fn getVersionManifest(&self, id: &str, version: &str) -> Result<JsonValue, String>;
+
+    /// Runs once the files of a version are downloaded into `./{id}`
+    fn postProcess(&self, _id: &str, _versionManifest: &JsonValue) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Reads the targets of a downloaded version, by default from the targets of its manifest
+    fn getTargets(&self, _id: &str, versionManifest: &JsonValue) -> Result<Targets, String> {
+        let mut mcVersion: Option<String> = None;
+        let mut loader: Option<(String, String)> = None;
+        for target in versionManifest["targets"].members() {
+            match target["type"].as_str() {
+                Some("game") => mcVersion = Some(target["version"].to_string()),
+                Some("modloader") => loader = Some((target["name"].to_string(), target["version"].to_string())),
+                _ => {}
+            }
+        }
+        let minecraft = mcVersion.ok_or("Version manifest has no game target")?;
+        Ok(Targets { minecraft, loader })
+    }
+
+    /// The id of the newest version of a pack
+    fn getLatestVersion(&self, id: &str) -> Result<String, String> {
+        let packManifest = self.getPackManifest(id)?;
+        match self.listVersions(&packManifest)?.first() {
+            Some(new) => Ok(new["id"].to_string()),
+            None => Err("Failed to retrieve latest version".to_string())
+        }
+    }
+}
+
+/// Packs published by FTB
+pub struct FTB {
+    /// Base url of the modpacks.ch API
+    api: String
+}
+
+impl FTB {
+    pub fn new(api: &str) -> Self {
+        Self { api: api.to_string() }
+    }
+
+    /// The url FTB pack endpoints are below
+    pub fn url(&self) -> String {
+        format!("{}/modpack/", self.api)
+    }
+
+    /// Lists the ids of the top packs of a list such as featured or popular/plays
+    pub fn listPacks(&self, list: &str, what: &str) -> Result<Vec<String>, String> {
+        let data = getJson(&format!("{}{list}/10", self.url()), what)?;
+        if !data["packs"].is_array() {
+            return Err("Invalid format".to_string());
+        }
+        Ok(data["packs"].members().map(|it| it.to_string()).collect())
+    }
+}
+
+/// Curseforge packs mirrored by modpacks.ch
+pub struct Curseforge {
+    /// Base url of the modpacks.ch API
+    api: String,
+    /// Base url of the curseforge API single files are looked up in
+    filesApi: String,
+    /// Key the curseforge API is called with
+    apiKey: Option<String>
+}
+
+impl Curseforge {
+    pub fn new(api: &str, filesApi: &str, apiKey: Option<String>) -> Self {
+        Self { api: api.to_string(), filesApi: filesApi.to_string(), apiKey }
+    }
+
+    /// Whether single files can be looked up, the official API refuses requests without a key
+    /// while other servers set with curseforge-api-url may not need one
+    pub fn canGetFiles(&self) -> bool {
+        self.apiKey.is_some() || self.filesApi != DEFAULT_CURSEFORGE_API_URL
+    }
+
+    /// The url curseforge pack endpoints are below
+    pub fn url(&self) -> String {
+        format!("{}/curseforge/", self.api)
+    }
+
+    /// Looks up a file of a curseforge project in the manifest file format, with the size and SHA-1 it's verified against
+    pub fn getFile(&self, project: i64, fileId: i64) -> Result<JsonValue, String> {
+        if !self.canGetFiles() {
+            return Err(format!("Looking up curseforge file {project}/{fileId} needs an API key, set curseforge-api-key in config.toml or $CURSEFORGE_API_KEY"));
+        }
+        let url = format!("{}/mods/{project}/files/{fileId}", self.filesApi);
+        let headers: Vec<(&str, &str)> = self.apiKey.iter().map(|key| ("x-api-key", key.as_str())).collect();
+        let file = json::parse(&cache::getMetadata(&url, &headers, "curseforge file")?)
+            .map_err(|it| format!("Failed to parse curseforge file as json: {:?}", it))?;
+        let file = &file["data"];
+        let name = file["fileName"].as_str()
+            .filter(|name| !name.is_empty() && !name.contains(['/', '\\']) && *name != "..")
+            .ok_or(format!("File {project}/{fileId} has no valid name"))?;
+        // Algorithm 1 is SHA-1, the other one is MD5
+        let sha1 = file["hashes"].members()
+            .find(|hash| hash["algo"] == 1)
+            .map(|hash| hash["value"].to_string())
+            .unwrap_or_default();
+        let url = match file["downloadUrl"].as_str().filter(|url| !url.is_empty()) {
+            Some(url) => url.to_string(),
+            // Files the API hides the url of are still on the CDN, below a path derived from the file id
+            None => format!("https://edge.forgecdn.net/files/{}/{}/{name}", fileId / 1000, fileId % 1000)
+        };
+        Ok(object! {
+            path: "./",
+            name: name,
+            url: url,
+            sha1: sha1,
+            size: file["fileLength"].clone(),
+            curseforge: object! { project: project, file: fileId }
+        })
+    }
+}
+
+impl PackSource for FTB {
+    fn name(&self) -> &str {
+        "FTB"
+    }
+
+    fn search(&self, term: &str) -> Result<Vec<String>, String> {
+        Ok(searchModpacksCh(&self.api, term)?["packs"].members().map(|it| it.to_string()).collect())
+    }
+
+    fn getPackManifest(&self, id: &str) -> Result<JsonValue, String> {
+        getJson(&format!("{}{id}", self.url()), "FTB modpack manifest")
+    }
+
+    fn listVersions(&self, packManifest: &JsonValue) -> Result<Vec<JsonValue>, String> {
+        // FTB lists versions oldest first
+        Ok(getVersions(packManifest)?.into_iter().rev().collect())
+    }
+
+    fn getVersionManifest(&self, id: &str, version: &str) -> Result<JsonValue, String> {
+        getVersionJson(&format!("{}{id}/{version}", self.url()))
+    }
+}
+
+impl PackSource for Curseforge {
+    fn name(&self) -> &str {
+        "Curseforge"
+    }
+
+    fn search(&self, term: &str) -> Result<Vec<String>, String> {
+        let data = searchModpacksCh(&self.api, term)?;
+        if !data["curseforge"].is_array() {
+            return Err("Invalid format".to_string());
+        }
+        Ok(data["curseforge"].members().map(|it| it.to_string()).collect())
+    }
+
+    fn getPackManifest(&self, id: &str) -> Result<JsonValue, String> {
+        getJson(&format!("{}{id}", self.url()), "Curseforge modpack manifest")
+    }
+
+    fn listVersions(&self, packManifest: &JsonValue) -> Result<Vec<JsonValue>, String> {
+        getVersions(packManifest)
+    }
+
+    fn getVersionManifest(&self, id: &str, version: &str) -> Result<JsonValue, String> {
+        getVersionJson(&format!("{}{id}/{version}", self.url()))
+    }
+
+    /// Curseforge packs ship their configs and other non mod files in overrides.zip
+    fn postProcess(&self, id: &str, _versionManifest: &JsonValue) -> Result<(), String> {
+        println!("Extracting overrides");
+        let basePath = format!("./{id}/");
+        let file = File::open(basePath.clone() + "overrides.zip")
+            .map_err(|it| format!("Failed to open overrides file: {:?}", it))?;
+        extractZip(file, &basePath, Some("overrides"), false)
+    }
+
+    /// Curseforge packs have their targets in the manifest.json they ship
+    fn getTargets(&self, id: &str, _versionManifest: &JsonValue) -> Result<Targets, String> {
+        let mut file = File::open(format!("./{}/manifest.json", id))
+            .map_err(|it| format!("Failed to open manifest: {:?}", it))?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf).map_err(|it| format!("Failed to read manifest: {:?}", it))?;
+        let manifest = json::parse(&buf).map_err(|it| format!("Manifest is invalid: {:?}", it))?;
+        let mcSection = &manifest["minecraft"];
+        let loader = mcSection["modLoaders"][0]["id"].as_str()
+            .and_then(|loader| loader.split_once('-'))
+            .map(|(name, version)| (name.to_string(), version.to_string()));
+        Ok(Targets { minecraft: mcSection["version"].to_string(), loader })
+    }
+}
+
+/// modpacks.ch searches FTB and curseforge packs at once
+fn searchModpacksCh(api: &str, term: &str) -> Result<JsonValue, String> {
+    getJson(&format!("{api}/modpack/search/5?term={term}"), "modpack search")
+}
+
+fn getVersions(packManifest: &JsonValue) -> Result<Vec<JsonValue>, String> {
+    match &packManifest["versions"] {
+        JsonValue::Array(versions) => Ok(versions.clone()),
+        _ => Err("\"versions\" is not an array".to_string())
+    }
+}
+
+fn getJson(url: &str, what: &str) -> Result<JsonValue, String> {
+    let decoded = cache::getMetadata(url, &[], what)?;
+    json::parse(&decoded)
+        .map_err(|it| format!("Failed to parse {what} as json: {:?}", it))
+}
+
+fn getVersionJson(url: &str) -> Result<JsonValue, String> {
+    // API returns empty url without the user agent of the official launcher
+    let decoded = cache::getMetadata(url, &[("User-Agent", USER_AGENT)], "modpack version manifest")?;
+    json::parse(&decoded)
+        .map_err(|it| format!("Failed to parse modpack version manifest as json: {:?}", it))
+}
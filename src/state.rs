@@ -0,0 +1,65 @@
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path
+};
+
+use json::{object, JsonValue};
+
+use crate::{cache, Targets};
+
+/// Written to the root of every installation, records what was installed so it can be exported later
+pub const STATE_FILE: &str = ".modpacks-rs.json";
+
+/// Records an installation of `files` from the given pack version into `./{id}`, `source` names where the pack comes from
+pub fn write(id: &str, source: &str, versionManifest: &JsonValue, files: &[&JsonValue], targets: Option<&Targets>) -> Result<(), String> {
+    let mut recorded = JsonValue::new_array();
+    for file in files {
+        let mut entry = object! {
+            path: filePath(file),
+            sha1: file["sha1"].clone(),
+            size: file["size"].clone(),
+            url: file["url"].clone(),
+            clientonly: file["clientonly"].as_bool().unwrap_or(false),
+            serveronly: file["serveronly"].as_bool().unwrap_or(false)
+        };
+        if file["curseforge"].is_object() {
+            entry["curseforge"] = file["curseforge"].clone();
+        }
+        recorded.push(entry).unwrap();
+    }
+    let mut state = object! {
+        type: source,
+        pack: id,
+        version: versionManifest["id"].clone(),
+        versionName: versionManifest["name"].clone(),
+        files: recorded
+    };
+    if let Some(targets) = targets {
+        state["minecraft"] = targets.minecraft.clone().into();
+        if let Some((name, version)) = &targets.loader {
+            state["loader"] = object! { name: name.clone(), version: version.clone() };
+        }
+    }
+    File::create(format!("./{id}/{STATE_FILE}"))
+        .and_then(|mut file| file.write_all(json::stringify_pretty(state, 4).as_bytes()))
+        .map_err(|it| format!("Failed to write install state: {:?}", it))?;
+    if let Err(error) = cache::track(Path::new(&format!("./{id}"))) {
+        eprintln!("{}", error);
+    }
+    Ok(())
+}
+
+/// Reads the install state of a directory installed by us
+pub fn read(dir: &Path) -> Result<JsonValue, String> {
+    let contents = fs::read_to_string(dir.join(STATE_FILE))
+        .map_err(|it| format!("{:?} was not installed by modpacks-rs, failed to read {STATE_FILE}: {:?}", dir, it))?;
+    json::parse(&contents).map_err(|it| format!("Install state is invalid: {:?}", it))
+}
+
+/// The path of a manifest file relative to the installation, such as mods/example.jar
+pub fn filePath(file: &JsonValue) -> String {
+    let dir = file["path"].to_string();
+    let dir = dir.trim_start_matches("./").trim_start_matches('/');
+    format!("{dir}{}", file["name"])
+}
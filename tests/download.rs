@@ -0,0 +1,811 @@
+//! Downloads packs with the binary against a local stand-in for the modpacks.ch API
+
+#![allow(non_snake_case)]
+
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    io::{Cursor, Read, Write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex},
+    thread
+};
+
+use json::{array, object, JsonValue};
+use sha1::{Digest, Sha1};
+use sha2::Sha512;
+use tiny_http::{Header, Response, Server};
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+/// Serves fixed responses by path, a route with several responses serves them in order and then keeps serving the last one,
+/// responses have an ETag and requests that already have the response get a 304,
+/// the curseforge API below /cf refuses requests without the API key `CURSEFORGE_API_KEY` like the official one does
+struct MockServer {
+    url: String,
+    routes: Arc<Mutex<HashMap<String, Vec<Vec<u8>>>>>,
+    requests: Arc<Mutex<Vec<String>>>,
+    notModified: Arc<AtomicUsize>
+}
+
+impl MockServer {
+    fn start() -> Self {
+        let server = Server::http("127.0.0.1:0").expect("Failed to start mock server");
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        let routes: Arc<Mutex<HashMap<String, Vec<Vec<u8>>>>> = Arc::default();
+        let requests: Arc<Mutex<Vec<String>>> = Arc::default();
+        let notModified: Arc<AtomicUsize> = Arc::default();
+        let (routesClone, requestsClone, notModifiedClone) = (Arc::clone(&routes), Arc::clone(&requests), Arc::clone(&notModified));
+        thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let path = request.url().split('?').next().unwrap().to_string();
+                requestsClone.lock().unwrap().push(path.clone());
+                if path.starts_with("/cf/") && !request.headers().iter().any(|it| it.field.equiv("x-api-key") && it.value == CURSEFORGE_API_KEY) {
+                    let _ = request.respond(Response::from_string("Forbidden").with_status_code(403));
+                    continue;
+                }
+                let body = routesClone.lock().unwrap().get_mut(&path).map(|responses| {
+                    if responses.len() > 1 { responses.remove(0) } else { responses[0].clone() }
+                });
+                let _ = match body {
+                    Some(body) => {
+                        let etag = format!("\"{}\"", sha1(&body));
+                        let header = Header::from_bytes("ETag", etag.as_str()).unwrap();
+                        if request.headers().iter().any(|it| it.field.equiv("If-None-Match") && it.value == etag) {
+                            notModifiedClone.fetch_add(1, Ordering::SeqCst);
+                            // Left without validators, so clients have to keep the ones they have
+                            request.respond(Response::empty(304))
+                        } else {
+                            request.respond(Response::from_data(body).with_header(header))
+                        }
+                    }
+                    None => request.respond(Response::from_string("Not found").with_status_code(404))
+                };
+            }
+        });
+        Self { url, routes, requests, notModified }
+    }
+
+    fn serve(&self, path: &str, responses: Vec<Vec<u8>>) {
+        self.routes.lock().unwrap().insert(path.to_string(), responses);
+    }
+
+    fn serveJson(&self, path: &str, value: JsonValue) {
+        self.serve(path, vec![json::stringify(value).into_bytes()]);
+    }
+
+    fn requestCount(&self, path: &str) -> usize {
+        self.requests.lock().unwrap().iter().filter(|request| *request == path).count()
+    }
+
+    /// A manifest file served by this server at /files/{name}
+    fn file(&self, path: &str, name: &str, contents: &[u8]) -> JsonValue {
+        self.serve(&format!("/files/{name}"), vec![contents.to_vec()]);
+        object! {
+            path: path,
+            name: name,
+            url: format!("{}/files/{name}", self.url),
+            sha1: sha1(contents),
+            size: contents.len(),
+            clientonly: false,
+            serveronly: false
+        }
+    }
+}
+
+/// The curseforge API key the mock server accepts
+const CURSEFORGE_API_KEY: &str = "test-api-key";
+
+/// A fresh working directory for the binary, with empty config and cache directories so local settings don't leak in
+fn workDir(name: &str) -> WorkDir {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = env::temp_dir().join(format!("modpacks-rs-test-{}-{}-{name}", std::process::id(), COUNTER.fetch_add(1, Ordering::SeqCst)));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("config")).unwrap();
+    WorkDir(dir)
+}
+
+/// Removes the working directory and everything installed or cached in it once a test is done
+struct WorkDir(PathBuf);
+
+impl Deref for WorkDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn run(dir: &Path, server: &MockServer, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_modpacks-rs"))
+        .args(["--api-url", &server.url])
+        .args(args)
+        .current_dir(dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .env_remove("MODPACKS_API_URL")
+        .env_remove("CURSEFORGE_API_KEY")
+        .env_remove("HTTPS_PROXY")
+        .env_remove("https_proxy")
+        .env_remove("HTTP_PROXY")
+        .env_remove("http_proxy")
+        .env_remove("ALL_PROXY")
+        .env_remove("all_proxy")
+        .output()
+        .expect("Failed to run modpacks-rs");
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+fn sha1(contents: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(contents);
+    hex::encode(hasher.finalize())
+}
+
+fn zip(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in entries {
+        archive.start_file(*name, FileOptions::default()).unwrap();
+        archive.write_all(contents).unwrap();
+    }
+    archive.finish().unwrap().into_inner()
+}
+
+fn readState(dir: &Path) -> JsonValue {
+    json::parse(&fs::read_to_string(dir.join(".modpacks-rs.json")).unwrap()).unwrap()
+}
+
+/// Points the curseforge file API at `server`, below /cf
+fn useCurseforgeApi(dir: &Path, server: &MockServer) {
+    fs::create_dir_all(dir.join("config/modpacks-rs")).unwrap();
+    let config = format!("curseforge-api-url = \"{}/cf\"\ncurseforge-api-key = \"{CURSEFORGE_API_KEY}\"\n", server.url);
+    fs::write(dir.join("config/modpacks-rs/config.toml"), config).unwrap();
+}
+
+/// Serves `contents` as file `fileId` of curseforge project `project`, described like the curseforge API does
+fn curseforgeFile(server: &MockServer, project: i64, fileId: i64, name: &str, contents: &[u8]) {
+    server.serve(&format!("/files/{name}"), vec![contents.to_vec()]);
+    server.serveJson(&format!("/cf/mods/{project}/files/{fileId}"), object! {
+        data: object! {
+            id: fileId,
+            fileName: name,
+            fileLength: contents.len(),
+            downloadUrl: format!("{}/files/{name}", server.url),
+            hashes: array![object! { value: "d41d8cd98f00b204e9800998ecf8427e", algo: 2 }, object! { value: sha1(contents), algo: 1 }]
+        }
+    });
+}
+
+fn ftbVersion(id: i64, name: &str, files: JsonValue) -> JsonValue {
+    object! {
+        id: id,
+        name: name,
+        files: files,
+        targets: array![
+            object! { type: "game", name: "minecraft", version: "1.20.1" },
+            object! { type: "modloader", name: "forge", version: "47.2.0" }
+        ]
+    }
+}
+
+#[test]
+fn ftbLatestIsTheLastListedVersion() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/100", object! {
+        name: "Test pack",
+        versions: array![object! { id: 1, name: "1.0" }, object! { id: 2, name: "2.0" }]
+    });
+    server.serveJson("/modpack/100/1", ftbVersion(1, "1.0", array![]));
+    let files = array![
+        server.file("./mods/", "a.jar", b"mod a"),
+        server.file("./config/", "a.cfg", b"config a")
+    ];
+    server.serveJson("/modpack/100/2", ftbVersion(2, "2.0", files));
+
+    let dir = workDir("ftb-latest");
+    assert!(run(&dir, &server, &["ftb", "download", "100", "latest"]).status.success());
+    assert_eq!(server.requestCount("/modpack/100/1"), 0);
+    assert_eq!(fs::read(dir.join("100/mods/a.jar")).unwrap(), b"mod a");
+    assert_eq!(fs::read(dir.join("100/config/a.cfg")).unwrap(), b"config a");
+    let state = readState(&dir.join("100"));
+    assert_eq!(state["type"], "FTB");
+    assert_eq!(state["versionName"], "2.0");
+    assert_eq!(state["minecraft"], "1.20.1");
+    assert_eq!(state["loader"]["name"], "forge");
+}
+
+#[test]
+fn ftbClientKeepsServerOnlyFiles() {
+    let server = MockServer::start();
+    let mut serverOnly = server.file("./mods/", "server.jar", b"server mod");
+    serverOnly["serveronly"] = true.into();
+    let files = array![server.file("./mods/", "client.jar", b"client mod"), serverOnly];
+    server.serveJson("/modpack/101/5", ftbVersion(5, "5.0", files));
+
+    let dir = workDir("ftb-side");
+    assert!(run(&dir, &server, &["--threads", "4", "ftb", "download", "101", "5"]).status.success());
+    assert!(dir.join("101/mods/client.jar").exists());
+    assert!(dir.join("101/mods/server.jar").exists());
+}
+
+#[test]
+fn curseforgeLatestIsTheFirstListedVersionAndOverridesAreExtracted() {
+    let server = MockServer::start();
+    server.serveJson("/curseforge/200", object! {
+        name: "Curseforge pack",
+        versions: array![object! { id: 11, name: "1.1" }, object! { id: 10, name: "1.0" }]
+    });
+    let manifest = object! {
+        minecraft: object! { version: "1.19.2", modLoaders: array![object! { id: "forge-43.3.0", primary: true }] },
+        overrides: "overrides"
+    };
+    let manifest = json::stringify(manifest);
+    let overrides = zip(&[
+        ("overrides/config/b.cfg", b"config b"),
+        ("overrides/options.txt", b"options")
+    ]);
+    let files = array![
+        server.file("./mods/", "b.jar", b"mod b"),
+        server.file("./", "manifest.json", manifest.as_bytes()),
+        server.file("./", "overrides.zip", &overrides)
+    ];
+    server.serveJson("/curseforge/200/11", object! { id: 11, name: "1.1", files: files });
+
+    let dir = workDir("cf-latest");
+    assert!(run(&dir, &server, &["cf", "download", "200", "latest"]).status.success());
+    assert_eq!(server.requestCount("/curseforge/200/10"), 0);
+    assert_eq!(fs::read(dir.join("200/mods/b.jar")).unwrap(), b"mod b");
+    assert_eq!(fs::read(dir.join("200/config/b.cfg")).unwrap(), b"config b");
+    assert_eq!(fs::read(dir.join("200/options.txt")).unwrap(), b"options");
+    let state = readState(&dir.join("200"));
+    assert_eq!(state["type"], "Curseforge");
+    assert_eq!(state["minecraft"], "1.19.2");
+    assert_eq!(state["loader"]["version"], "43.3.0");
+}
+
+#[test]
+fn mismatchedHashesAreRetried() {
+    for threads in ["1", "4"] {
+        let server = MockServer::start();
+        let file = server.file("./mods/", "flaky.jar", b"flaky mod");
+        server.serve("/files/flaky.jar", vec![b"corrupt".to_vec(), b"corrupt".to_vec(), b"flaky mod".to_vec()]);
+        server.serveJson("/modpack/102/1", ftbVersion(1, "1.0", array![file]));
+
+        let dir = workDir("retry");
+        assert!(run(&dir, &server, &["--threads", threads, "ftb", "download", "102", "1"]).status.success());
+        assert_eq!(server.requestCount("/files/flaky.jar"), 3);
+        assert_eq!(fs::read(dir.join("102/mods/flaky.jar")).unwrap(), b"flaky mod");
+    }
+}
+
+#[test]
+fn persistentlyMismatchedHashesFail() {
+    let server = MockServer::start();
+    let file = server.file("./mods/", "broken.jar", b"broken mod");
+    server.serve("/files/broken.jar", vec![b"corrupt  !".to_vec()]);
+    server.serveJson("/modpack/103/1", ftbVersion(1, "1.0", array![file]));
+
+    let dir = workDir("mismatch");
+    let output = run(&dir, &server, &["ftb", "download", "103", "1"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Mismatched hashes"));
+    assert_eq!(server.requestCount("/files/broken.jar"), 6);
+    assert!(!dir.join("103/mods/broken.jar").exists());
+}
+
+#[test]
+fn sharedFilesAreReusedFromTheCache() {
+    let server = MockServer::start();
+    let shared = server.file("./mods/", "shared.jar", b"shared mod");
+    let mut config = server.file("./config/", "shared.cfg", b"shared config");
+    server.serveJson("/modpack/104/1", ftbVersion(1, "1.0", array![shared.clone(), config.clone()]));
+    config["name"] = "renamed.cfg".into();
+    server.serveJson("/modpack/105/1", ftbVersion(1, "1.0", array![shared, config]));
+
+    let dir = workDir("cache");
+    assert!(run(&dir, &server, &["ftb", "download", "104", "1"]).status.success());
+    assert!(run(&dir, &server, &["ftb", "download", "105", "1"]).status.success());
+    assert_eq!(server.requestCount("/files/shared.jar"), 1);
+    assert_eq!(server.requestCount("/files/shared.cfg"), 1);
+    assert_eq!(fs::read(dir.join("105/mods/shared.jar")).unwrap(), b"shared mod");
+    assert_eq!(fs::read(dir.join("105/config/renamed.cfg")).unwrap(), b"shared config");
+
+    // Configs are copied, so editing one pack's doesn't change the cache or other packs
+    fs::write(dir.join("104/config/shared.cfg"), b"edited").unwrap();
+    assert!(run(&dir, &server, &["ftb", "download", "105", "1"]).status.success());
+    assert_eq!(server.requestCount("/files/shared.cfg"), 1);
+    assert_eq!(fs::read(dir.join("105/config/renamed.cfg")).unwrap(), b"shared config");
+
+    assert!(run(&dir, &server, &["--no-cache", "ftb", "download", "105", "1"]).status.success());
+    assert_eq!(server.requestCount("/files/shared.jar"), 2);
+}
+
+#[test]
+fn pruningKeepsFilesOfTrackedInstallations() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/106/1", ftbVersion(1, "1.0", array![server.file("./mods/", "kept.jar", b"kept mod")]));
+    server.serveJson("/modpack/107/1", ftbVersion(1, "1.0", array![server.file("./mods/", "removed.jar", &[0; 4096])]));
+
+    let dir = workDir("prune");
+    assert!(run(&dir, &server, &["ftb", "download", "106", "1"]).status.success());
+    assert!(run(&dir, &server, &["ftb", "download", "107", "1"]).status.success());
+    let entry = |contents: &[u8]| {
+        let hash = sha1(contents);
+        dir.join("cache/modpacks-rs/files").join(&hash[..2]).join(hash)
+    };
+    assert!(entry(b"kept mod").exists() && entry(&[0; 4096]).exists());
+
+    // Only the cached API responses can go
+    assert!(run(&dir, &server, &["cache", "prune", "--max-size", "0"]).status.success());
+    assert!(entry(b"kept mod").exists() && entry(&[0; 4096]).exists());
+    assert_eq!(fs::read_dir(dir.join("cache/modpacks-rs/metadata")).map_or(0, |entries| entries.count()), 0);
+
+    fs::remove_dir_all(dir.join("107")).unwrap();
+    let output = run(&dir, &server, &["cache", "clear"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Removed 1 files, reclaimed 4.0 KiB"));
+    assert!(entry(b"kept mod").exists());
+    assert!(!entry(&[0; 4096]).exists());
+}
+
+#[test]
+fn apiResponsesAreCachedAndRevalidated() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/108/1", ftbVersion(1, "1.0", array![server.file("./mods/", "a.jar", b"mod a")]));
+
+    let dir = workDir("metadata");
+    assert!(run(&dir, &server, &["ftb", "download", "108", "1"]).status.success());
+    assert!(run(&dir, &server, &["ftb", "download", "108", "1"]).status.success());
+    assert_eq!(server.requestCount("/modpack/108/1"), 1);
+
+    assert!(run(&dir, &server, &["--refresh", "ftb", "download", "108", "1"]).status.success());
+    assert_eq!(server.requestCount("/modpack/108/1"), 2);
+    assert_eq!(server.notModified.load(Ordering::SeqCst), 1);
+    assert!(run(&dir, &server, &["--refresh", "ftb", "download", "108", "1"]).status.success());
+    assert_eq!(server.notModified.load(Ordering::SeqCst), 2);
+
+    fs::create_dir_all(dir.join("config/modpacks-rs")).unwrap();
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "metadata-ttl = \"0s\"\n").unwrap();
+    server.serveJson("/modpack/108/1", ftbVersion(1, "1.1", array![]));
+    assert!(run(&dir, &server, &["ftb", "download", "108", "1"]).status.success());
+    assert_eq!(server.requestCount("/modpack/108/1"), 4);
+    assert_eq!(readState(&dir.join("108"))["versionName"], "1.1");
+}
+
+#[test]
+fn proxyIsUsedExceptForNoProxyHosts() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/109/1", ftbVersion(1, "1.0", array![server.file("./mods/", "a.jar", b"mod a")]));
+    // Nothing listens on the discard port, so requests through the proxy fail
+    let dir = workDir("proxy");
+    fs::create_dir_all(dir.join("config/modpacks-rs")).unwrap();
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "proxy = \"http://127.0.0.1:9\"\nconnect-timeout = \"5s\"\n").unwrap();
+    assert!(!run(&dir, &server, &["--no-cache", "ftb", "download", "109", "1"]).status.success());
+    assert_eq!(server.requestCount("/modpack/109/1"), 0);
+
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "proxy = \"http://127.0.0.1:9\"\nno-proxy = \"localhost, 127.0.0.1\"\n").unwrap();
+    assert!(run(&dir, &server, &["--no-cache", "ftb", "download", "109", "1"]).status.success());
+    assert_eq!(server.requestCount("/modpack/109/1"), 1);
+}
+
+#[test]
+fn downloadsAreThrottledTogether() {
+    let server = MockServer::start();
+    let files = array![
+        server.file("./mods/", "a.jar", &[1; 100 * 1024]),
+        server.file("./mods/", "b.jar", &[2; 100 * 1024])
+    ];
+    server.serveJson("/modpack/110/1", ftbVersion(1, "1.0", files));
+
+    let dir = workDir("rate");
+    let start = std::time::Instant::now();
+    assert!(run(&dir, &server, &["--threads", "2", "--limit-rate", "100K", "ftb", "download", "110", "1"]).status.success());
+    // 200 KiB at 100 KiB/s takes two seconds, less the burst allowance
+    assert!(start.elapsed().as_millis() >= 1500, "took {:?}", start.elapsed());
+    assert!(dir.join("110/mods/b.jar").exists());
+}
+
+#[test]
+fn failedDownloadsFallBackToMirrorsBeforeRetrying() {
+    let server = MockServer::start();
+    let file = server.file("./mods/", "mirrored.jar", b"mirrored mod");
+    server.serve("/files/mirrored.jar", vec![b"corrupt".to_vec()]);
+    server.serve("/mirror/files/mirrored.jar", vec![b"mirrored mod".to_vec()]);
+    server.serveJson("/modpack/111/1", ftbVersion(1, "1.0", array![file]));
+
+    let dir = workDir("mirror");
+    let mirror = format!("{}/mirror/", server.url);
+    assert!(run(&dir, &server, &["--mirror", &mirror, "ftb", "download", "111", "1"]).status.success());
+    assert_eq!(server.requestCount("/files/mirrored.jar"), 1);
+    assert_eq!(server.requestCount("/mirror/files/mirrored.jar"), 1);
+    assert_eq!(fs::read(dir.join("111/mods/mirrored.jar")).unwrap(), b"mirrored mod");
+}
+
+#[test]
+fn filesWithTheWrongSizeAreRejected() {
+    let server = MockServer::start();
+    let mut file = server.file("./mods/", "sized.jar", b"sized mod");
+    file["sha1"] = "".into();
+    server.serve("/files/sized.jar", vec![b"truncated".to_vec()[..5].to_vec(), b"sized mod".to_vec()]);
+    server.serveJson("/modpack/112/1", ftbVersion(1, "1.0", array![file]));
+
+    let dir = workDir("size");
+    assert!(run(&dir, &server, &["ftb", "download", "112", "1"]).status.success());
+    assert_eq!(server.requestCount("/files/sized.jar"), 2);
+    assert_eq!(fs::read(dir.join("112/mods/sized.jar")).unwrap(), b"sized mod");
+}
+
+#[test]
+fn filesWithoutHashesFailInStrictMode() {
+    let server = MockServer::start();
+    let mut file = server.file("./mods/", "unhashed.jar", b"unhashed mod");
+    file["sha1"] = "".into();
+    server.serveJson("/modpack/113/1", ftbVersion(1, "1.0", array![file]));
+
+    let dir = workDir("strict");
+    let output = run(&dir, &server, &["--strict", "ftb", "download", "113", "1"]);
+    assert!(!output.status.success());
+    assert_eq!(server.requestCount("/files/unhashed.jar"), 0);
+
+    let output = run(&dir, &server, &["ftb", "download", "113", "1"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unhashed.jar has no hash"));
+}
+
+#[test]
+fn projectConfigAndFlagsOverrideTheUserConfig() {
+    let server = MockServer::start();
+    let dir = workDir("config");
+    fs::create_dir_all(dir.join("config/modpacks-rs")).unwrap();
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "threads = 2\nlimit-rate = \"1M\"\nuser-agent = \"test\"\n").unwrap();
+    fs::write(dir.join("modpacks.toml"), "threads = 4\nlimit-rate = \"2M\"\ncurseforge-api-key = \"secret\"\n").unwrap();
+
+    let output = run(&dir, &server, &["--limit-rate", "3M", "--strict", "config", "show"]);
+    assert!(output.status.success());
+    let shown = String::from_utf8_lossy(&output.stdout);
+    assert!(shown.contains("config.toml") && shown.contains("modpacks.toml"));
+    let settings: toml::Table = shown.parse().unwrap();
+    assert_eq!(settings["threads"].as_integer(), Some(4));
+    assert_eq!(settings["limit-rate"].as_str(), Some("3M"));
+    assert_eq!(settings["user-agent"].as_str(), Some("test"));
+    assert_eq!(settings["strict"].as_bool(), Some(true));
+    assert_eq!(settings["api-url"].as_str(), Some(server.url.as_str()));
+    assert_eq!(settings["curseforge-api-key"].as_str(), Some("<hidden>"));
+    assert!(!shown.contains("secret"));
+}
+
+#[test]
+fn curseforgeServersUseTheVerifiedServerPack() {
+    let server = MockServer::start();
+    let pack = zip(&[("Pack 1.0/mods/a.jar", b"mod a"), ("Pack 1.0/config/a.cfg", b"config a")]);
+    curseforgeFile(&server, 210, 5001, "Pack-1.0-server.zip", &pack);
+    server.serve("/files/Pack-1.0-server.zip", vec![b"corrupt".to_vec(), pack]);
+    server.serveJson("/curseforge/210/12", object! { id: 12, name: "1.0", serverPackFileId: 5001, files: array![] });
+    server.serveJson("/curseforge/210/13", object! { id: 13, name: "1.1", files: array![] });
+
+    let dir = workDir("cf-server-pack");
+    useCurseforgeApi(&dir, &server);
+    assert!(run(&dir, &server, &["cf", "server", "210", "12", "--server-pack"]).status.success());
+    assert_eq!(server.requestCount("/files/Pack-1.0-server.zip"), 2);
+    assert_eq!(fs::read(dir.join("210/mods/a.jar")).unwrap(), b"mod a");
+    assert_eq!(fs::read(dir.join("210/config/a.cfg")).unwrap(), b"config a");
+
+    let output = run(&dir, &server, &["cf", "server", "210", "13", "--server-pack"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("This version has no server pack"));
+}
+
+#[test]
+fn curseforgeServerPacksNeedAnApiKey() {
+    let server = MockServer::start();
+    let files = array![server.file("./mods/", "a.jar", b"mod a")];
+    let manifest = object! { id: 12, name: "1.0", serverPackFileId: 5001, files: files };
+    server.serveJson("/curseforge/211/12", manifest);
+
+    let dir = workDir("cf-server-pack-key");
+    let output = run(&dir, &server, &["cf", "server", "211", "12", "--server-pack"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs an API key, set curseforge-api-key"));
+
+    // Without --server-pack the server is built from the client files, which stops short of the modloader without a manifest.json
+    let output = run(&dir, &server, &["cf", "server", "211", "12"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipping the server pack"));
+    assert_eq!(fs::read(dir.join("211/mods/a.jar")).unwrap(), b"mod a");
+}
+
+#[test]
+fn instancesCanBeDownloadedAgain() {
+    let server = MockServer::start();
+    server.file("./", "icon.png", b"icon");
+    server.serveJson("/modpack/114", object! {
+        name: "Instance pack",
+        art: array![object! { type: "square", url: format!("{}/files/icon.png", server.url) }],
+        versions: array![object! { id: 1, name: "1.0" }]
+    });
+    let files = array![server.file("./mods/", "a.jar", b"mod a"), server.file("./config/", "a.cfg", b"config a")];
+    server.serveJson("/modpack/114/1", ftbVersion(1, "1.0", files));
+
+    let dir = workDir("instance");
+    for _ in 0..2 {
+        assert!(run(&dir, &server, &["ftb", "download", "114", "1", "--instance", "prism"]).status.success());
+    }
+    let instance = dir.join("114");
+    assert_eq!(fs::read(instance.join(".minecraft/mods/a.jar")).unwrap(), b"mod a");
+    assert_eq!(fs::read(instance.join(".minecraft/config/a.cfg")).unwrap(), b"config a");
+    assert!(!instance.join("mods").exists());
+    assert!(instance.join("instance.cfg").exists() && instance.join("mmc-pack.json").exists());
+    assert!(instance.join("modpacks_114.png").exists());
+    for name in ["instance.cfg", "mmc-pack.json", "modpacks_114.png"] {
+        assert!(!instance.join(".minecraft").join(name).exists());
+    }
+}
+
+/// Reads an entry of a zip archive on disk
+fn readZipEntry(path: &Path, name: &str) -> Option<Vec<u8>> {
+    let mut archive = ZipArchive::new(fs::File::open(path).unwrap()).unwrap();
+    let mut entry = archive.by_name(name).ok()?;
+    let mut raw = vec![];
+    entry.read_to_end(&mut raw).unwrap();
+    Some(raw)
+}
+
+#[test]
+fn installsExportAsModrinthPacks() {
+    let server = MockServer::start();
+    let mut serverOnly = server.file("./mods/", "server.jar", b"server mod");
+    serverOnly["serveronly"] = true.into();
+    let mut clientOnly = server.file("./mods/", "client.jar", b"client mod");
+    clientOnly["clientonly"] = true.into();
+    let files = array![server.file("./mods/", "a.jar", b"mod a"), serverOnly, clientOnly, server.file("./config/", "a.cfg", b"config a")];
+    server.serveJson("/modpack/115/1", ftbVersion(1, "1.0", files));
+
+    let dir = workDir("export");
+    assert!(run(&dir, &server, &["ftb", "download", "115", "1"]).status.success());
+    fs::write(dir.join("115/config/a.cfg"), b"edited config").unwrap();
+    fs::write(dir.join("115/options.txt"), b"options").unwrap();
+    assert!(run(&dir, &server, &["export", "115", "--mrpack", "--output", "out.mrpack"]).status.success());
+
+    let output = dir.join("out.mrpack");
+    let index = json::parse(&String::from_utf8(readZipEntry(&output, "modrinth.index.json").unwrap()).unwrap()).unwrap();
+    let file = |path: &str| index["files"].members().find(|file| file["path"] == path).cloned().unwrap_or(JsonValue::Null);
+    let modA = file("mods/a.jar");
+    assert_eq!(modA["hashes"]["sha1"], sha1(b"mod a"));
+    assert_eq!(modA["hashes"]["sha512"], hex::encode(Sha512::digest(b"mod a")));
+    assert_eq!(modA["downloads"][0], format!("{}/files/a.jar", server.url));
+    assert_eq!(modA["fileSize"], 5);
+    assert_eq!(modA["env"]["client"], "required");
+    assert_eq!(file("mods/server.jar")["env"]["client"], "unsupported");
+    assert_eq!(file("mods/client.jar")["env"]["server"], "unsupported");
+    assert!(file("config/a.cfg").is_null());
+    assert_eq!(readZipEntry(&output, "overrides/config/a.cfg").unwrap(), b"edited config");
+    assert_eq!(readZipEntry(&output, "overrides/options.txt").unwrap(), b"options");
+    assert!(readZipEntry(&output, "overrides/mods/a.jar").is_none());
+}
+
+/// A modrinth pack with `files` and a forge dependency
+fn mrpack(files: JsonValue, entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let index = json::stringify(object! {
+        formatVersion: 1,
+        game: "minecraft",
+        versionId: "1.0",
+        name: "Modrinth pack",
+        files: files,
+        dependencies: object! { minecraft: "1.20.1", forge: "47.2.0" }
+    });
+    let mut entries = entries.to_vec();
+    entries.push(("modrinth.index.json", index.as_bytes()));
+    zip(&entries)
+}
+
+/// A modrinth index file served by `server`
+fn mrpackFile(server: &MockServer, path: &str, contents: &[u8], client: &str, serverSide: &str) -> JsonValue {
+    let name = path.rsplit('/').next().unwrap();
+    server.serve(&format!("/files/{name}"), vec![contents.to_vec()]);
+    object! {
+        path: path,
+        hashes: object! { sha1: sha1(contents), sha512: hex::encode(Sha512::digest(contents)) },
+        env: object! { client: client, server: serverSide },
+        downloads: array![format!("{}/files/{name}", server.url)],
+        fileSize: contents.len()
+    }
+}
+
+#[test]
+fn modrinthClientInstallsFilterByEnvAndApplyClientOverrides() {
+    let server = MockServer::start();
+    let files = array![
+        mrpackFile(&server, "mods/a.jar", b"mod a", "required", "required"),
+        mrpackFile(&server, "mods/server.jar", b"server mod", "unsupported", "required"),
+        mrpackFile(&server, "mods/client.jar", b"client mod", "required", "unsupported")
+    ];
+    let pack = mrpack(files, &[
+        ("overrides/config/a.cfg", b"config a"),
+        ("client-overrides/options.txt", b"client options"),
+        ("server-overrides/server.properties", b"motd=server")
+    ]);
+    let dir = workDir("mrpack");
+    fs::write(dir.join("pack.mrpack"), pack).unwrap();
+    assert!(run(&dir, &server, &["mrpack", "install", "pack.mrpack"]).status.success());
+
+    let installed = dir.join("pack");
+    assert_eq!(fs::read(installed.join("mods/a.jar")).unwrap(), b"mod a");
+    assert_eq!(fs::read(installed.join("mods/client.jar")).unwrap(), b"client mod");
+    assert!(!installed.join("mods/server.jar").exists());
+    assert_eq!(server.requestCount("/files/server.jar"), 0);
+    assert_eq!(fs::read(installed.join("config/a.cfg")).unwrap(), b"config a");
+    assert_eq!(fs::read(installed.join("options.txt")).unwrap(), b"client options");
+    assert!(!installed.join("server.properties").exists());
+    assert_eq!(readState(&installed)["loader"]["name"], "forge");
+}
+
+#[test]
+fn modrinthFilesOutsideThePackAreRefused() {
+    let server = MockServer::start();
+    let pack = mrpack(array![mrpackFile(&server, "../evil.jar", b"evil", "required", "required")], &[]);
+    let dir = workDir("mrpack-escape");
+    fs::write(dir.join("pack.mrpack"), pack).unwrap();
+    let output = run(&dir, &server, &["mrpack", "install", "pack.mrpack"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to install file outside of the pack"));
+    assert_eq!(server.requestCount("/files/evil.jar"), 0);
+    assert!(!dir.join("evil.jar").exists());
+}
+
+#[test]
+fn curseforgeZipsAreResolvedVerifiedAndExtracted() {
+    let server = MockServer::start();
+    curseforgeFile(&server, 300, 3001, "a.jar", b"mod a");
+    curseforgeFile(&server, 301, 3002, "shaders.zip", b"shaders");
+    curseforgeFile(&server, 302, 3003, "optional.jar", b"optional mod");
+    let manifest = json::stringify(object! {
+        minecraft: object! { version: "1.19.2", modLoaders: array![object! { id: "forge-43.3.0", primary: true }] },
+        name: "Zip pack",
+        version: "1.0",
+        overrides: "custom-overrides",
+        files: array![
+            object! { projectID: 300, fileID: 3001, required: true },
+            object! { projectID: 301, fileID: 3002, required: true },
+            object! { projectID: 302, fileID: 3003, required: false }
+        ]
+    });
+    let pack = zip(&[
+        ("manifest.json", manifest.as_bytes()),
+        ("custom-overrides/config/a.cfg", b"config a")
+    ]);
+
+    let dir = workDir("cf-zip");
+    useCurseforgeApi(&dir, &server);
+    fs::write(dir.join("pack.zip"), pack).unwrap();
+    let output = run(&dir, &server, &["--strict", "--threads", "2", "cf", "install-zip", "pack.zip"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("shaders.zip isn't a jar"));
+
+    let installed = dir.join("pack");
+    assert_eq!(fs::read(installed.join("mods/a.jar")).unwrap(), b"mod a");
+    assert_eq!(fs::read(installed.join("resourcepacks/shaders.zip")).unwrap(), b"shaders");
+    assert_eq!(server.requestCount("/cf/mods/302/files/3003"), 0);
+    assert!(!installed.join("mods/optional.jar").exists());
+    assert_eq!(fs::read(installed.join("config/a.cfg")).unwrap(), b"config a");
+    assert!(!installed.join("custom-overrides").exists());
+    let state = readState(&installed);
+    assert_eq!(state["minecraft"], "1.19.2");
+    assert_eq!(state["files"][0]["sha1"], sha1(b"mod a"));
+}
+
+#[test]
+fn curseforgeZipFilesWithoutHashesAreUnverified() {
+    let server = MockServer::start();
+    server.serve("/files/b.jar", vec![b"mod b".to_vec()]);
+    server.serveJson("/cf/mods/303/files/3004", object! {
+        data: object! { id: 3004, fileName: "b.jar", fileLength: 5, downloadUrl: format!("{}/files/b.jar", server.url), hashes: array![] }
+    });
+    let manifest = json::stringify(object! {
+        minecraft: object! { version: "1.19.2", modLoaders: array![] },
+        files: array![object! { projectID: 303, fileID: 3004, required: true }]
+    });
+    let dir = workDir("cf-zip-unhashed");
+    fs::write(dir.join("pack.zip"), zip(&[("manifest.json", manifest.as_bytes())])).unwrap();
+
+    let output = run(&dir, &server, &["cf", "install-zip", "pack.zip"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("needs an API key"));
+
+    useCurseforgeApi(&dir, &server);
+    let output = run(&dir, &server, &["--strict", "cf", "install-zip", "pack.zip"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to download files without a hash"));
+    let output = run(&dir, &server, &["cf", "install-zip", "pack.zip"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("b.jar has no hash"));
+    assert_eq!(fs::read(dir.join("pack/mods/b.jar")).unwrap(), b"mod b");
+}
+
+#[test]
+fn invalidConfigIsReportedWithoutPanicking() {
+    let server = MockServer::start();
+    let dir = workDir("bad-config");
+    fs::create_dir_all(dir.join("config/modpacks-rs")).unwrap();
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "limit-rate = \"fast\"\nca-bundle = \"missing.pem\"\n").unwrap();
+
+    assert!(run(&dir, &server, &["help"]).status.success());
+    let output = run(&dir, &server, &["config", "show"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("limit-rate = \"fast\""));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid config") && !stderr.contains("panicked"));
+
+    fs::write(dir.join("config/modpacks-rs/config.toml"), "threads = \"many\"\n").unwrap();
+    let output = run(&dir, &server, &["ftb", "download", "116", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("threads must be a number"));
+}
+
+#[test]
+#[cfg(not(windows))]
+fn installedServerFlagsAreRefusedForOtherPlatforms() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/117/1", ftbVersion(1, "1.0", array![]));
+    let dir = workDir("foreign-installer");
+    let output = run(&dir, &server, &["ftb", "server", "117", "1", "--platform", "windows", "--bundle", "--accept-eula"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--accept-eula, --bundle can't be used"));
+    assert_eq!(server.requestCount("/modpack/117/1"), 0);
+}
+
+#[test]
+fn strictRefusesTheUnverifiableFTBInstaller() {
+    let server = MockServer::start();
+    server.serveJson("/modpack/118/1", ftbVersion(1, "1.0", array![]));
+    let dir = workDir("strict-installer");
+    let output = run(&dir, &server, &["--strict", "ftb", "server", "118", "1", "--installer"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Refusing to download the FTB server installer in --strict mode"));
+    assert_eq!(server.requestCount("/modpack/118/1"), 0);
+}
+
+#[test]
+fn installsExportAsCurseforgeZips() {
+    let server = MockServer::start();
+    let manifest = json::stringify(object! {
+        minecraft: object! { version: "1.19.2", modLoaders: array![object! { id: "forge-43.3.0", primary: true }] },
+        overrides: "overrides"
+    });
+    let overrides = zip(&[("overrides/config/b.cfg", b"config b")]);
+    let mut modB = server.file("./mods/", "b.jar", b"mod b");
+    modB["curseforge"] = object! { project: 400, file: 4001 };
+    let files = array![
+        modB,
+        server.file("./mods/", "unlisted.jar", b"unlisted mod"),
+        server.file("./", "manifest.json", manifest.as_bytes()),
+        server.file("./", "overrides.zip", &overrides)
+    ];
+    server.serveJson("/curseforge/202/21", object! { id: 21, name: "2.1", files: files });
+
+    let dir = workDir("export-cf");
+    assert!(run(&dir, &server, &["cf", "download", "202", "21"]).status.success());
+    fs::write(dir.join("202/config/b.cfg"), b"edited config").unwrap();
+    assert!(run(&dir, &server, &["export", "202", "--output", "out.zip"]).status.success());
+
+    let output = dir.join("out.zip");
+    let exported = json::parse(&String::from_utf8(readZipEntry(&output, "manifest.json").unwrap()).unwrap()).unwrap();
+    assert_eq!(exported["minecraft"]["version"], "1.19.2");
+    assert_eq!(exported["minecraft"]["modLoaders"][0]["id"], "forge-43.3.0");
+    assert_eq!(exported["version"], "2.1");
+    assert_eq!(exported["overrides"], "overrides");
+    assert_eq!(exported["files"].len(), 1);
+    assert_eq!(exported["files"][0]["projectID"], 400);
+    assert_eq!(exported["files"][0]["fileID"], 4001);
+    assert_eq!(readZipEntry(&output, "overrides/config/b.cfg").unwrap(), b"edited config");
+    assert_eq!(readZipEntry(&output, "overrides/mods/unlisted.jar").unwrap(), b"unlisted mod");
+    assert!(readZipEntry(&output, "overrides/mods/b.jar").is_none());
+}